@@ -0,0 +1,24 @@
+#!/usr/bin/env python3
+# Example external tg-captain plugin. Reads requests from stdin and answers with one JSON line per request.
+import json
+import sys
+
+for line in sys.stdin:
+    request = json.loads(line)
+    if request["type"] == "command":
+        args = request["args"]
+        if args == "keyboard":
+            replies = [{
+                "type": "keyboard",
+                "text": "Pick one",
+                "buttons": [[{"text": "A", "data": "a"}, {"text": "B", "data": "b"}]],
+            }]
+        elif args == "file":
+            replies = [{"type": "document", "name": "echo.txt", "content": json.dumps(request, indent=2)}]
+        else:
+            replies = [{"type": "text", "text": args or "Usage: /echo <text|keyboard|file>"}]
+    elif request["type"] == "callback":
+        replies = [{"type": "text", "text": "You picked " + request["data"]}]
+    else:
+        replies = []
+    print(json.dumps({"replies": replies}), flush=True)
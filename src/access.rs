@@ -0,0 +1,204 @@
+use teloxide::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Role from the `roles` config section.
+///
+/// Permissions are dot separated paths like `docker.container.kill.web`. A pattern allows the path
+/// itself and everything below it, `*` matches any single segment. Dots inside command arguments
+/// are escaped as `%2E`, see `segment`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Role {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    users: Vec<u64>,
+    #[serde(default)]
+    groups: Vec<i64>,
+}
+
+impl Role {
+    fn allows(&self, permission: &str) -> bool {
+        self.allow.iter().any(|pattern| matches(pattern, permission))
+            && !self.deny.iter().any(|pattern| matches(pattern, permission))
+    }
+
+    fn assigned(&self, user: Option<UserId>, chat: Option<ChatId>) -> bool {
+        user.is_some_and(|user| self.users.contains(&user.0))
+            || chat.is_some_and(|chat| self.groups.contains(&chat.0))
+    }
+}
+
+/// Role based access control built from `admins` and `roles` config sections.
+pub struct AccessControl {
+    roles: HashMap<String, Role>,
+}
+
+impl AccessControl {
+    pub fn new(admins: Option<Vec<u64>>, roles: Option<HashMap<String, Role>>) -> Result<AccessControl, String> {
+        let mut roles = roles.unwrap_or_default();
+        if let Some(admins) = admins {
+            // Flat admin list is kept for compatibility, these users are allowed to do everything
+            let admin = roles.entry("admin".to_string()).or_insert_with(|| Role {
+                allow: vec!["*".to_string()],
+                ..Role::default()
+            });
+            admin.users.extend(admins);
+        }
+        if roles.values().all(|role| role.users.is_empty() && role.groups.is_empty()) {
+            return Err("Security activated but no admins or role members provided!".to_string());
+        }
+        Ok(AccessControl { roles })
+    }
+
+    fn assigned_roles(&self, upd: &Update) -> impl Iterator<Item = &Role> {
+        let user = upd.user().map(|user| user.id);
+        let chat = upd.chat().map(|chat| chat.id);
+        self.roles.values().filter(move |role| role.assigned(user, chat))
+    }
+
+    /// Users assigned to roles that allow the permission.
+    pub fn users_allowed(&self, permission: &str) -> Vec<u64> {
+        let mut users: Vec<u64> = self.roles.values()
+            .filter(|role| role.allows(permission))
+            .flat_map(|role| role.users.iter().copied())
+            .collect();
+        users.sort();
+        users.dedup();
+        users
+    }
+
+    /// Check that sender of the update can perform the action. Updates without permission
+    /// (not handled by any plugin) are allowed for every user that has at least one role.
+    pub fn allows(&self, upd: &Update, permission: Option<&str>) -> bool {
+        match permission {
+            Some(permission) => self.assigned_roles(upd).any(|role| role.allows(permission)),
+            None => self.assigned_roles(upd).next().is_some(),
+        }
+    }
+
+    /// Check every permission of the update, e.g. one per torrent matched by selector.
+    /// Empty list means that targets of the update couldn't be resolved, it is never allowed.
+    pub fn allows_all(&self, upd: &Update, permissions: Option<&[String]>) -> bool {
+        match permissions {
+            Some(permissions) => !permissions.is_empty() && permissions.iter().all(|permission| self.allows(upd, Some(permission))),
+            None => self.allows(upd, None),
+        }
+    }
+}
+
+/// Check permission against pattern from config.
+pub fn matches(pattern: &str, permission: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let permission: Vec<&str> = permission.split('.').collect();
+    pattern.len() <= permission.len()
+        && pattern.iter().zip(permission.iter()).all(|(expected, actual)| *expected == "*" || expected == actual)
+}
+
+/// Escape command argument used as permission segment, so `api.prod` is a single segment.
+pub fn segment(value: &str) -> String {
+    value.replace('%', "%25").replace('.', "%2E")
+}
+
+/// Reverse of `segment`.
+pub fn unescape(segment: &str) -> String {
+    segment.replace("%2E", ".").replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(user: u64, chat: i64) -> Update {
+        crate::test_utils::message(user, chat, "/sys")
+    }
+
+    fn role(allow: &[&str], deny: &[&str], users: &[u64], groups: &[i64]) -> Role {
+        Role {
+            allow: allow.iter().map(|pattern| pattern.to_string()).collect(),
+            deny: deny.iter().map(|pattern| pattern.to_string()).collect(),
+            users: users.to_vec(),
+            groups: groups.to_vec(),
+        }
+    }
+
+    #[test]
+    fn pattern_allows_path_and_everything_below() {
+        assert!(matches("docker", "docker.container.kill.web"));
+        assert!(matches("docker.container", "docker.container.kill.web"));
+        assert!(matches("docker.container.kill.web", "docker.container.kill.web"));
+        assert!(!matches("docker.container.kill.web", "docker.container.kill"));
+        assert!(!matches("docker.container.kill.web", "docker.container.kill.webhook"));
+        assert!(!matches("docker.image", "docker.container.list"));
+    }
+
+    #[test]
+    fn star_matches_everything_or_single_segment() {
+        assert!(matches("*", "sys.system"));
+        assert!(matches("docker.*.prune", "docker.image.prune"));
+        assert!(matches("transmission.remove.*.yes", "transmission.remove.5.yes"));
+        assert!(!matches("transmission.remove.*.yes", "transmission.remove.5.no"));
+        assert!(!matches("docker.*.prune", "docker.prune"));
+    }
+
+    #[test]
+    fn escaped_dots_stay_in_one_segment() {
+        let permission = format!("docker.container.restart.{}", segment("api.prod"));
+        assert_eq!(permission, "docker.container.restart.api%2Eprod");
+        assert!(!matches("docker.container.restart.api", &permission));
+        assert!(matches(&format!("docker.container.restart.{}", segment("api.prod")), &permission));
+        assert_eq!(unescape(&segment("50%.x")), "50%.x");
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let operator = role(&["docker", "sys"], &["docker.container.kill.db", "sys.shutdown"], &[1], &[]);
+        assert!(operator.allows("docker.container.kill.web"));
+        assert!(!operator.allows("docker.container.kill.db"));
+        assert!(!operator.allows("sys.shutdown"));
+        assert!(!operator.allows("transmission.list"));
+    }
+
+    #[test]
+    fn roles_are_assigned_by_user_and_group() {
+        let roles = HashMap::from([
+            ("viewer".to_string(), role(&["sys.system"], &[], &[], &[-100])),
+            ("operator".to_string(), role(&["docker"], &["docker.container.kill"], &[2], &[])),
+        ]);
+        let access = AccessControl::new(Some(vec![1]), Some(roles)).unwrap();
+
+        assert!(access.allows(&update(1, -200), Some("sys.shutdown")));
+        assert!(access.allows(&update(3, -100), Some("sys.system")));
+        assert!(!access.allows(&update(3, -100), Some("docker.container.list")));
+        assert!(access.allows(&update(2, -200), Some("docker.container.list")));
+        assert!(!access.allows(&update(2, -200), Some("docker.container.kill.web")));
+        assert!(access.allows(&update(2, -200), None));
+        assert!(!access.allows(&update(3, -200), None));
+        assert_eq!(access.users_allowed("docker.info"), vec![1, 2]);
+    }
+
+    #[test]
+    fn every_permission_must_be_allowed() {
+        let roles = HashMap::from([
+            ("operator".to_string(), role(&["transmission"], &["transmission.remove.5"], &[2], &[])),
+        ]);
+        let access = AccessControl::new(None, Some(roles)).unwrap();
+        let upd = update(2, -200);
+        let permissions = ["transmission.remove.4.no".to_string(), "transmission.remove.5.no".to_string()];
+        assert!(access.allows_all(&upd, Some(&permissions[..1])));
+        assert!(!access.allows_all(&upd, Some(&permissions)));
+        assert!(!access.allows_all(&upd, Some(&[])));
+        assert!(access.allows_all(&upd, None));
+    }
+
+    #[test]
+    fn security_requires_members() {
+        assert!(AccessControl::new(None, None).is_err());
+        assert!(AccessControl::new(Some(vec![1]), None).is_ok());
+    }
+}
@@ -0,0 +1,253 @@
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{di::DependencySupplier, HandlerDescription},
+    prelude::*,
+    types::{Me, UpdateKind},
+    RequestError,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use crate::plugin::{Plugins, UpdateHandler};
+
+/// `audit` config section.
+#[derive(Deserialize, Debug)]
+pub struct AuditConfig {
+    path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure { error: String },
+    Denied,
+    /// Waiting for confirmation, the outcome is recorded once it is answered.
+    Pending,
+    Cancelled,
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    timestamp: String,
+    user_id: Option<u64>,
+    username: Option<String>,
+    chat_id: Option<i64>,
+    plugin: Option<String>,
+    command: String,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+tokio::task_local! {
+    /// Outcome reported by the handler that is currently running under `wrap`.
+    static OUTCOME: RefCell<Option<Outcome>>;
+}
+
+/// Mark the update handled by the current task as failed and return the message for the user.
+/// Handlers report most errors to the chat and return `Ok`, so the audit can't see them otherwise.
+pub fn failure(message: String) -> String {
+    let _ = OUTCOME.try_with(|outcome| {
+        outcome.borrow_mut().get_or_insert_with(|| Outcome::Failure { error: message.clone() });
+    });
+    message
+}
+
+/// Mark the update handled by the current task as waiting for confirmation. It is recorded
+/// by the confirmation itself, so `wrap` doesn't record it as handled.
+pub fn pending() {
+    let _ = OUTCOME.try_with(|outcome| {
+        outcome.borrow_mut().get_or_insert(Outcome::Pending);
+    });
+}
+
+/// Append-only JSON lines log of handled updates.
+pub struct Audit {
+    path: String,
+    file: Mutex<File>,
+}
+
+impl Audit {
+    pub fn open(config: AuditConfig) -> Result<Audit, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .map_err(|x| format!("couldn't open audit log {}: {}", config.path, x))?;
+        Ok(Audit {
+            path: config.path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, upd: &Update, plugins: &Plugins, me: &Me, outcome: Outcome) {
+        let user = upd.user();
+        let entry = Entry {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            user_id: user.map(|user| user.id.0),
+            username: user.and_then(|user| user.username.clone()),
+            chat_id: upd.chat().map(|chat| chat.id.0),
+            plugin: plugins.iter().find(|plugin| plugin.permission(upd, me).is_some()).map(|plugin| plugin.name().to_string()),
+            command: update_text(upd),
+            outcome,
+        };
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(x) => {
+                log::error!("Failed to serialize audit entry: {}", x);
+                return;
+            }
+        };
+        if let Err(x) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            log::error!("Failed to write audit log: {}", x);
+        }
+    }
+
+    /// Run action that was confirmed after `upd` was handled and record its outcome for `upd`.
+    pub async fn run<F: Future<Output = String>>(&self, upd: &Update, plugins: &Plugins, me: &Me, action: F) -> String {
+        let (text, outcome) = capture(action).await;
+        self.record(upd, plugins, me, outcome.unwrap_or(Outcome::Success));
+        text
+    }
+
+    /// Last `count` entries, optionally only of the user with given id or username.
+    pub fn recent(&self, count: usize, user: Option<&str>) -> String {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(x) => return failure(format!("Failed with err: {}", x)),
+        };
+        let user = user.map(|user| user.trim_start_matches('@'));
+        let entries: Vec<Entry> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<Entry>(&line).ok())
+            .filter(|entry| match user {
+                Some(user) => entry.user_id.is_some_and(|id| id.to_string() == user)
+                    || entry.username.as_deref() == Some(user),
+                None => true,
+            })
+            .collect();
+
+        let mut message = String::new();
+        for entry in entries.iter().skip(entries.len().saturating_sub(count)) {
+            let outcome = match &entry.outcome {
+                Outcome::Success => "success".to_string(),
+                Outcome::Failure { error } => format!("failure: {}", error),
+                Outcome::Denied => "denied".to_string(),
+                Outcome::Pending => "pending".to_string(),
+                Outcome::Cancelled => "cancelled".to_string(),
+                Outcome::Expired => "expired".to_string(),
+            };
+            message += format!("{} {} ({}) in {}: {} -> {}\n",
+                entry.timestamp,
+                entry.username.as_deref().map(|username| format!("@{}", username)).unwrap_or("N/A".to_string()),
+                entry.user_id.map(|id| id.to_string()).unwrap_or("N/A".to_string()),
+                entry.chat_id.map(|id| id.to_string()).unwrap_or("N/A".to_string()),
+                entry.command,
+                outcome
+            ).as_str();
+        }
+        if message.is_empty() {
+            "No audit entries".to_string()
+        } else {
+            message
+        }
+    }
+}
+
+/// Wrap handler so every update handled by it is recorded with its outcome.
+pub fn wrap(audit: Arc<Audit>, handler: UpdateHandler) -> UpdateHandler {
+    dptree::from_fn_with_description(DpHandlerDescription::entry(), move |deps: DependencyMap, cont| {
+        let audit = audit.clone();
+        async move {
+            let (result, reported): (ControlFlow<Result<(), RequestError>, DependencyMap>, _) = capture(cont(deps.clone())).await;
+            if let ControlFlow::Break(outcome) = &result {
+                let outcome = match (outcome, reported) {
+                    (Err(x), _) => Outcome::Failure { error: x.to_string() },
+                    (Ok(()), Some(Outcome::Pending)) => return result,
+                    (Ok(()), Some(outcome)) => outcome,
+                    (Ok(()), None) => Outcome::Success,
+                };
+                let upd: Arc<Update> = deps.get();
+                let plugins: Arc<Plugins> = deps.get();
+                let me: Arc<Me> = deps.get();
+                audit.record(&upd, &plugins, &me, outcome);
+            }
+            result
+        }
+    })
+    .chain(handler)
+}
+
+/// Output of the future with outcome reported by it, see `failure` and `pending`.
+async fn capture<F: Future>(future: F) -> (F::Output, Option<Outcome>) {
+    OUTCOME.scope(RefCell::new(None), async {
+        let output = future.await;
+        (output, OUTCOME.with(|outcome| outcome.take()))
+    }).await
+}
+
+/// Text of the update as it was sent by user: message text, document name or callback data.
+pub fn update_text(upd: &Update) -> String {
+    match &upd.kind {
+        UpdateKind::Message(msg) => msg.text()
+            .or(msg.caption())
+            .map(|text| text.to_string())
+            .or(msg.document().map(|doc| format!("<document {}>", doc.file_name.clone().unwrap_or_default())))
+            .unwrap_or_default(),
+        UpdateKind::CallbackQuery(query) => query.data.clone().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{me, message};
+
+    async fn outcome_of(name: &str, handler: UpdateHandler) -> String {
+        let path = std::env::temp_dir().join(format!("tg-captain-audit-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let audit = Arc::new(Audit::open(AuditConfig { path: path.to_string_lossy().to_string() }).unwrap());
+        let plugins: Plugins = Arc::new(Vec::new());
+        let _ = wrap(audit.clone(), handler).dispatch(dptree::deps![message(7, -1, "/docker container stop web"), plugins, me()]).await;
+        let recent = audit.recent(1, Some("user"));
+        let _ = std::fs::remove_file(&path);
+        recent
+    }
+
+    #[tokio::test]
+    async fn reported_failure_is_recorded() {
+        let handler = dptree::endpoint(|| async {
+            let _message = failure("Failed with err: no such container".to_string());
+            Ok(())
+        });
+        assert!(outcome_of("failure", handler).await.ends_with("-> failure: Failed with err: no such container\n"));
+    }
+
+    #[tokio::test]
+    async fn handled_update_is_recorded_as_success() {
+        let handler = dptree::endpoint(|| async { Ok(()) });
+        assert!(outcome_of("success", handler).await.ends_with("/docker container stop web -> success\n"));
+    }
+
+    #[tokio::test]
+    async fn pending_update_is_left_to_confirmation() {
+        let handler = dptree::endpoint(|| async {
+            pending();
+            Ok(())
+        });
+        assert_eq!(outcome_of("pending", handler).await, "No audit entries");
+    }
+
+    #[test]
+    fn failure_outside_of_audit_is_returned_as_is() {
+        assert_eq!(failure("Failed with err: x".to_string()), "Failed with err: x");
+    }
+}
@@ -0,0 +1,264 @@
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, Me, UpdateKind},
+};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::access;
+use crate::audit::{self, Audit, Outcome};
+use crate::plugin::{self, Plugins, Resolved, UpdateHandler};
+use crate::utils::split_message;
+
+/// `confirmation` config section. Commands are permission patterns, see `access::Role`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConfirmationConfig {
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    #[serde(default = "default_commands")]
+    commands: Vec<String>,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        ConfirmationConfig {
+            timeout: default_timeout(),
+            commands: default_commands(),
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn default_commands() -> Vec<String> {
+    [
+        "sys.shutdown",
+        "sys.reboot",
+        "sys.sleep",
+        "sys.hibernate",
+        "docker.container.kill",
+        "docker.container.update",
+        "docker.*.prune",
+        "transmission.remove.*.yes",
+        "transmission.remove.*.y",
+    ].iter().map(|command| command.to_string()).collect()
+}
+
+/// What is done when the request is confirmed.
+enum Action {
+    /// Command held by `guard` is dispatched to the guarded handler.
+    Dispatch,
+    /// Action requested by a plugin with `Confirmation::ask`, its result is sent to the chat.
+    Run(BoxFuture<'static, String>),
+}
+
+struct Pending {
+    update: Update,
+    me: Me,
+    resolved: Resolved,
+    user: Option<UserId>,
+    text: String,
+    action: Action,
+}
+
+/// Requests waiting for confirmation, shared with plugins through dispatcher dependencies.
+pub struct Confirmation {
+    config: ConfirmationConfig,
+    audit: Option<Arc<Audit>>,
+    plugins: Plugins,
+    pending: Mutex<HashMap<u64, Pending>>,
+    next_id: AtomicU64,
+}
+
+impl Confirmation {
+    pub fn new(config: ConfirmationConfig, audit: Option<Arc<Audit>>, plugins: Plugins) -> Arc<Confirmation> {
+        Arc::new(Confirmation {
+            config,
+            audit,
+            plugins,
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Ask the user who sent `upd` to confirm `text` with inline keyboard. `action` runs only when
+    /// it is confirmed, its result is sent to the chat and recorded in audit for `upd`.
+    pub async fn ask(
+        self: &Arc<Self>,
+        bot: &Bot,
+        upd: &Update,
+        me: &Me,
+        text: String,
+        action: impl Future<Output = String> + Send + 'static) -> ResponseResult<()> {
+        // Request is recorded as pending here, the handler asking for it isn't recorded as handled
+        audit::pending();
+        self.request(bot.clone(), upd.clone(), me.clone(), Resolved::default(), text, Action::Run(Box::pin(action))).await
+    }
+
+    /// Every request is recorded in audit as pending and then with the outcome of its answer:
+    /// cancelled, expired or the outcome of the confirmed command.
+    async fn request(self: &Arc<Self>, bot: Bot, upd: Update, me: Me, resolved: Resolved, text: String, action: Action) -> ResponseResult<()> {
+        let Some(chat_id) = upd.chat().map(|chat| chat.id) else {
+            return Ok(());
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let keyboard = InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback("Confirm", format!("confirm:{}:yes", id)),
+            InlineKeyboardButton::callback("Cancel", format!("confirm:{}:no", id)),
+        ]]);
+        let sent = bot
+            .send_message(chat_id, format!("Please confirm: {}\nExpires in {}s", text, self.config.timeout))
+            .reply_markup(keyboard)
+            .await?;
+
+        self.record(&upd, &me, Outcome::Pending);
+        self.pending.lock().unwrap().insert(id, Pending {
+            user: upd.user().map(|user| user.id),
+            update: upd,
+            me,
+            resolved,
+            text,
+            action,
+        });
+
+        let confirmation = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(confirmation.config.timeout)).await;
+            let expired = confirmation.pending.lock().unwrap().remove(&id);
+            if let Some(pending) = expired {
+                confirmation.record(&pending.update, &pending.me, Outcome::Expired);
+                if let Err(x) = bot.edit_message_text(chat_id, sent.id, format!("Expired: {}", pending.text)).await {
+                    log::warn!("Failed to expire confirmation: {}", x);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn record(&self, upd: &Update, me: &Me, outcome: Outcome) {
+        if let Some(audit) = &self.audit {
+            audit.record(upd, &self.plugins, me, outcome);
+        }
+    }
+
+    fn required(&self, resolved: &Resolved, plugins: &Plugins, upd: &Update, me: &Me) -> bool {
+        if !matches!(upd.kind, UpdateKind::Message(_)) || plugin::confirms(plugins, upd, me) {
+            return false;
+        }
+        match &resolved.0 {
+            Some(permissions) => permissions.iter()
+                .any(|permission| self.config.commands.iter().any(|pattern| access::matches(pattern, permission))),
+            None => false,
+        }
+    }
+}
+
+/// Wrap handler so commands from the `confirmation` config are executed only after
+/// they are confirmed with inline keyboard by the same user.
+pub fn guard(confirmation: Arc<Confirmation>, handler: UpdateHandler) -> UpdateHandler {
+    let filter_confirmation = confirmation.clone();
+    let ask_confirmation = confirmation.clone();
+    let confirmed_handler = handler.clone();
+
+    let answer_closure = move |bot, query, me, plugins| {
+        answer(bot, query, me, plugins, confirmation.clone(), confirmed_handler.clone())
+    };
+    let ask_closure = move |bot, upd, me, resolved| {
+        ask(bot, upd, me, resolved, ask_confirmation.clone())
+    };
+
+    dptree::entry()
+        .branch(
+            Update::filter_callback_query()
+                .filter(|query: CallbackQuery| query.data.as_ref().is_some_and(|data| data.starts_with("confirm:")))
+                .endpoint(answer_closure),
+        )
+        .branch(
+            dptree::filter(move |resolved: Resolved, plugins: Plugins, upd: Update, me: Me| {
+                filter_confirmation.required(&resolved, &plugins, &upd, &me)
+            })
+            .endpoint(ask_closure),
+        )
+        .branch(handler)
+}
+
+async fn ask(bot: Bot, upd: Update, me: Me, resolved: Resolved, confirmation: Arc<Confirmation>) -> ResponseResult<()> {
+    let UpdateKind::Message(msg) = &upd.kind else {
+        return Ok(());
+    };
+    let text = msg.text().unwrap_or_default().to_string();
+    confirmation.request(bot, upd, me, resolved, text, Action::Dispatch).await
+}
+
+async fn answer(
+    bot: Bot,
+    query: CallbackQuery,
+    me: Me,
+    plugins: Plugins,
+    confirmation: Arc<Confirmation>,
+    handler: UpdateHandler) -> ResponseResult<()> {
+    let data = query.data.clone().unwrap_or_default();
+    let parts: Vec<&str> = data.split(':').collect();
+    let id: u64 = match parts.get(1).and_then(|id| id.parse().ok()) {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let confirmed = parts.get(2) == Some(&"yes");
+
+    let (pending, foreign) = {
+        let mut pending = confirmation.pending.lock().unwrap();
+        if pending.get(&id).is_some_and(|request| request.user.is_some_and(|user| user != query.from.id)) {
+            (None, true)
+        } else {
+            (pending.remove(&id), false)
+        }
+    };
+    if foreign {
+        bot.answer_callback_query(query.id).text("Only the user who sent the command can confirm it").await?;
+        return Ok(());
+    }
+    let Some(pending) = pending else {
+        bot.answer_callback_query(query.id).text("Confirmation expired").await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(query.id).await?;
+
+    if let Some(msg) = &query.message {
+        let status = if confirmed { "Confirmed" } else { "Cancelled" };
+        bot.edit_message_text(msg.chat.id, msg.id, format!("{}: {}", status, pending.text)).await?;
+    }
+    if !confirmed {
+        confirmation.record(&pending.update, &pending.me, Outcome::Cancelled);
+        return Ok(());
+    }
+
+    match pending.action {
+        Action::Dispatch => match handler.dispatch(dptree::deps![bot, me, plugins, confirmation.clone(), pending.update, pending.resolved]).await {
+            ControlFlow::Break(result) => result,
+            ControlFlow::Continue(_) => {
+                log::warn!("Confirmed command was not handled: {}", pending.text);
+                Ok(())
+            }
+        },
+        Action::Run(action) => {
+            let text = match &confirmation.audit {
+                Some(audit) => audit.run(&pending.update, &plugins, &me, action).await,
+                None => action.await,
+            };
+            if let Some(chat) = pending.update.chat() {
+                for part in split_message(&text) {
+                    bot.send_message(chat.id, part).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
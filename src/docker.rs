@@ -1,37 +1,205 @@
 use teloxide::{
     prelude::*,
-    dispatching::DpHandlerDescription,
-    utils::command::BotCommands, RequestError,
+    types::{BotCommand, InputFile},
+    utils::command::BotCommands,
 };
-use bollard::{API_DEFAULT_VERSION, container::{KillContainerOptions, PruneContainersOptions, RenameContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions}, image::{ListImagesOptions, PruneImagesOptions}, network::{ListNetworksOptions, PruneNetworksOptions}, volume::{ListVolumesOptions, PruneVolumesOptions}, Docker};
-use std::collections::HashMap;
+use bollard::{API_DEFAULT_VERSION, container::{Config, CreateContainerOptions, InspectContainerOptions, KillContainerOptions, LogsOptions, MemoryStatsStats, NetworkingConfig, PruneContainersOptions, RemoveContainerOptions, RenameContainerOptions, RestartContainerOptions, StartContainerOptions, Stats, StatsOptions, StopContainerOptions}, image::{CreateImageOptions, ListImagesOptions, PruneImagesOptions}, network::{ConnectNetworkOptions, ListNetworksOptions, PruneNetworksOptions}, volume::{ListVolumesOptions, PruneVolumesOptions}, exec::{CreateExecOptions, StartExecResults}, models::{ContainerInspectResponse, ContainerSummary, EndpointSettings, HostConfig, MountPoint, MountPointTypeEnum}, Docker};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use tokio::process::Command as Process;
+
+use crate::access::{segment, unescape};
+use crate::audit;
+use crate::docker_events::{self, EventsConfig};
+use crate::plugin::{parse_config, Plugin, UpdateHandler};
+use crate::utils::{format_size, parse_duration, split_message, MESSAGE_LIMIT};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command{
+    #[command(description = "manage docker containers, images, networks and volumes")]
     Docker (String)
 }
 
-pub fn get_short_help()-> String{
-    return "Docker plugin. Usage /docker [mode]. For detail help /docker help".to_string();
+#[derive(Deserialize, Debug)]
+struct DockerConfig {
+    mode: String,
+    path: Option<String>,
+    events: Option<EventsConfig>,
+    exec: Option<ExecConfig>,
+    #[serde(default)]
+    compose: ComposeConfig,
 }
 
-pub fn get_update_handler(mode:&String, value: &String) -> Handler<'static, DependencyMap, Result<(), RequestError>, DpHandlerDescription>{
-    let docker = get_docker(mode, value);
-    let command_closure = move |bot, msg, cmd| {
-        command_handler(bot, msg, cmd, docker.clone())
-    };
-    Update::filter_message()
-    .branch(dptree::entry().filter_command::<Command>().endpoint(command_closure))
+/// `docker.exec` config section, exec is disabled without it.
+#[derive(Deserialize, Debug)]
+struct ExecConfig {
+    /// Seconds before command output stops being collected.
+    #[serde(default = "default_exec_timeout")]
+    timeout: u64,
+    /// Allowed commands by container name, `*` matches every container or command.
+    /// Command must be equal to allowed one, command ending with ` *` also allows any arguments.
+    allow: HashMap<String, Vec<String>>,
+}
+
+fn default_exec_timeout() -> u64 {
+    60
+}
+
+impl ExecConfig {
+    fn allowed(&self, container: &str, command: &str) -> bool {
+        [container, "*"].iter()
+            .filter_map(|name| self.allow.get(*name))
+            .flatten()
+            .any(|allowed| match allowed.strip_suffix(" *") {
+                Some(prefix) => command == prefix || command.starts_with(&format!("{} ", prefix)),
+                None => allowed == "*" || command == allowed,
+            })
+    }
+}
+
+/// `docker.compose` config section, `up` and `pull` are only available for listed projects.
+#[derive(Deserialize, Debug, Default)]
+struct ComposeConfig {
+    /// Compose file path by project name.
+    #[serde(default)]
+    projects: HashMap<String, String>,
+    /// Seconds before `docker compose` process is killed.
+    #[serde(default = "default_compose_timeout")]
+    timeout: u64,
+}
+
+fn default_compose_timeout() -> u64 {
+    600
+}
+
+const PROJECT_LABEL: &str = "com.docker.compose.project";
+const SERVICE_LABEL: &str = "com.docker.compose.service";
+
+#[derive(Default)]
+pub struct DockerPlugin {
+    /// Client used to resolve container names in permissions, set by `update_handler`.
+    docker: OnceLock<Docker>,
+}
+
+#[async_trait]
+impl Plugin for DockerPlugin {
+    fn name(&self) -> &str {
+        "docker"
+    }
+
+    fn config_schema(&self) -> Option<&'static str> {
+        Some(r#"docker:
+  mode: <unix, http or default>
+  path: <path to unix socket or http server>
+  events: #optional
+    chats: [<chat-id>]
+    containers: [<container name>]
+    labels: [<label key or key=value>]
+    restart_loop: {count: <starts>, minutes: <window>}
+  exec: #optional
+    timeout: <seconds>
+    allow:
+      <container name or *>: [<command>, <command with any arguments> *, *]
+  compose: #optional
+    timeout: <seconds>
+    projects:
+      <project name>: <path to compose file>"#)
+    }
+
+    fn short_help(&self) -> String {
+        "Docker plugin. Usage /docker [mode]. For detail help /docker help".to_string()
+    }
+
+    fn help(&self) -> String {
+        get_docker_command_help_text()
+    }
+
+    fn commands(&self) -> Vec<BotCommand> {
+        Command::bot_commands()
+    }
+
+    fn action<'a>(&self, mut args: Vec<&'a str>) -> Vec<&'a str> {
+        match args.first().copied() {
+            None => args = vec!["info"],
+            Some("container") | Some("image") | Some("network") | Some("volume") | Some("compose") => {
+                if args.len() < 2 {
+                    args.push("list");
+                } else if args[1] == "det" {
+                    args[1] = "detail";
+                }
+            },
+            _ => {}
+        }
+        args
+    }
+
+    fn update_handler(&self, config: Option<&serde_yml::Value>) -> Result<UpdateHandler, String> {
+        let config: DockerConfig = parse_config(self, config)?;
+        let path = config.path.unwrap_or_default();
+        let docker = get_docker(&config.mode, &path);
+        let _ = self.docker.set(docker.clone());
+        let exec = config.exec.map(Arc::new);
+        let compose = Arc::new(config.compose);
+        let docker_host = (config.mode == "http").then_some(path);
+        let command_closure = move |bot, msg, cmd| {
+            command_handler(bot, msg, cmd, docker.clone(), exec.clone(), compose.clone(), docker_host.clone())
+        };
+        Ok(Update::filter_message()
+        .branch(dptree::entry().filter_command::<Command>().endpoint(command_closure)))
+    }
+
+    /// Container given by ID prefix or full id is replaced with its name, e.g.
+    /// `docker.container.kill.3f2a` becomes `docker.container.kill.db`.
+    async fn resolve(&self, permission: String) -> Vec<String> {
+        let Some(docker) = self.docker.get() else {
+            return vec![permission];
+        };
+        let mut segments: Vec<String> = permission.split('.').map(|segment| segment.to_string()).collect();
+        if segments.get(1).map(|segment| segment.as_str()) != Some("container") {
+            return vec![permission];
+        }
+        let targets = match segments.get(2).map(|segment| segment.as_str()) {
+            Some("stats") => 3..segments.len(),
+            Some("detail" | "stop" | "start" | "pause" | "unpause" | "kill" | "restart" | "rename" | "logs" | "exec" | "update") => 3..segments.len().min(4),
+            _ => 0..0,
+        };
+        for index in targets {
+            let name = unescape(&segments[index]);
+            if name.is_empty() || name == "cpu" || name == "mem" {
+                continue;
+            }
+            if let Ok(Some(canonical)) = docker.inspect_container(&name, None::<InspectContainerOptions>).await.map(|info| info.name) {
+                segments[index] = segment(canonical.trim_start_matches('/'));
+            }
+        }
+        vec![segments.join(".")]
+    }
+
+    fn start(&self, bot: Bot, config: Option<&serde_yml::Value>) -> Result<(), String> {
+        let config: DockerConfig = parse_config(self, config)?;
+        match config.events {
+            Some(events_config) => {
+                let docker = get_docker(&config.mode, &config.path.unwrap_or_default());
+                docker_events::start(bot, docker, events_config)
+            },
+            None => Ok(()),
+        }
+    }
 }
 
-fn get_docker(mode: &String, value: &String) -> Docker{
+fn get_docker(mode: &str, value: &str) -> Docker{
     if mode == "default"{
         Docker::connect_with_local_defaults().unwrap()
     } else if mode == "unix"{
         Docker::connect_with_socket("/var/run/docker.sock", 120, API_DEFAULT_VERSION).unwrap()
     } else if mode == "http"{
-        Docker::connect_with_http(value.as_str(), 120, API_DEFAULT_VERSION).unwrap()
+        Docker::connect_with_http(value, 120, API_DEFAULT_VERSION).unwrap()
     } else {
         Docker::connect_with_local_defaults().unwrap()
     }
@@ -41,25 +209,32 @@ async fn command_handler(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    docker: Docker) -> ResponseResult<()>{
+    docker: Docker,
+    exec: Option<Arc<ExecConfig>>,
+    compose: Arc<ComposeConfig>,
+    docker_host: Option<String>) -> ResponseResult<()>{
     match cmd {
         Command::Docker(data) => {
-            let com: Vec<&str> = data.trim().split(" ").collect();
-            if com[0] == "info" || com[0] == ""{
+            let com: Vec<&str> = data.split_whitespace().collect();
+            if com.is_empty() || com[0] == "info"{
                 bot.send_message(msg.chat.id, get_docker_info(&docker).await).await?;
             } else if com[0] == "container"{
-                container_command_handler(&bot, &msg, &com, &docker).await?;
+                container_command_handler(&bot, &msg, &com, &docker, exec.as_deref()).await?;
+            } else if com[0] == "updates" {
+                for part in split_message(&check_updates(&docker).await) {
+                    bot.send_message(msg.chat.id, part).await?;
+                }
+            } else if com[0] == "compose" {
+                compose_command_handler(&bot, &msg, &com, &docker, &compose, docker_host.as_deref()).await?;
             } else if com[0] == "image" {
                 image_command_handler(&bot, &msg, &com, &docker).await?;
             } else if com[0] == "network" {
                 network_command_handler(&bot, &msg, &com, &docker).await?;
             } else if com[0] == "volume"{
                 volumes_command_handler(&bot, &msg, &com, &docker).await?;
-            } else if com[0] == "help"{
-                bot.send_message(msg.chat.id, get_docker_command_help_text()).await?;
             } else {
                 bot.send_message(msg.chat.id, get_docker_command_help_text()).await?;
-            } 
+            }
         }
     }
     Ok(())
@@ -70,13 +245,15 @@ async fn get_docker_info(docker: &Docker) -> String{
     format!("OS: {}\nKernel: {}\nPlatform: {:?}\nVersion: {}\nApi: {}\nArch: {}", data.os.unwrap(), data.kernel_version.unwrap(), data.platform.unwrap().name, data.version.unwrap(), data.api_version.unwrap(), data.arch.unwrap())
 }
 
-async fn container_command_handler(bot: &Bot, msg: &Message, com: &Vec<&str>, docker: &Docker)-> ResponseResult<()>{
+async fn container_command_handler(bot: &Bot, msg: &Message, com: &[&str], docker: &Docker, exec: Option<&ExecConfig>)-> ResponseResult<()>{
     if com.len() >= 2{
-        if com[1] == "list" || com[1] == ""{
-            bot.send_message(msg.chat.id, get_containers_info(&docker).await).await?;
+        if com[1] == "list" || com[1].is_empty(){
+            bot.send_message(msg.chat.id, get_containers_info(docker).await).await?;
         } else if com[1] == "detail" || com[1] == "det"{
             if com.len() >=3 {
-                bot.send_message(msg.chat.id, get_container_details(docker, com[2].to_string()).await).await?;
+                for part in split_message(&get_container_details(docker, com[2].to_string()).await) {
+                    bot.send_message(msg.chat.id, part).await?;
+                }
             } else {
                 bot.send_message(msg.chat.id, "Please provide container name").await?;
             }
@@ -124,9 +301,45 @@ async fn container_command_handler(bot: &Bot, msg: &Message, com: &Vec<&str>, do
             }
         } else if com[1] == "prune"{
             bot.send_message(msg.chat.id, prune_container(docker).await).await?;
+        } else if com[1] == "stats"{
+            for part in split_message(&container_stats(docker, &com[2..]).await) {
+                bot.send_message(msg.chat.id, part).await?;
+            }
+        } else if com[1] == "exec"{
+            let command: Vec<&str> = com.iter().skip(3).copied().collect();
+            if com.len() < 3 || command.is_empty() {
+                bot.send_message(msg.chat.id, "Please provide container name and command").await?;
+            } else if let Some(exec) = exec.filter(|exec| exec.allowed(com[2], &command.join(" "))) {
+                match exec_container(docker, com[2], command, exec.timeout).await {
+                    Ok(output) => send_output(bot, msg.chat.id, output, &format!("{}-exec.log", com[2])).await?,
+                    Err(x) => {bot.send_message(msg.chat.id, audit::failure(x)).await?;},
+                }
+            } else {
+                bot.send_message(msg.chat.id, format!("Command is not allowed in {} by docker.exec config", com[2])).await?;
+            }
+        } else if com[1] == "update"{
+            if com.len() >= 3 {
+                bot.send_message(msg.chat.id, format!("Pulling image of {}", com[2])).await?;
+                let message = match update_container(docker, com[2]).await {
+                    Ok(message) => message,
+                    Err(x) => audit::failure(x),
+                };
+                bot.send_message(msg.chat.id, message).await?;
+            } else {
+                bot.send_message(msg.chat.id, "Please provide container name").await?;
+            }
+        } else if com[1] == "logs"{
+            if com.len() >= 3 {
+                match container_logs(docker, com[2], &com[3..]).await {
+                    Ok(logs) => send_output(bot, msg.chat.id, logs, &format!("{}.log", com[2])).await?,
+                    Err(x) => {bot.send_message(msg.chat.id, audit::failure(x)).await?;},
+                }
+            } else {
+                bot.send_message(msg.chat.id, "Please provide container name").await?;
+            }
         }
     } else {
-        bot.send_message(msg.chat.id, get_containers_info(&docker).await).await?;
+        bot.send_message(msg.chat.id, get_containers_info(docker).await).await?;
     }
     Ok(())
 }
@@ -144,91 +357,512 @@ async fn get_containers_info(docker: &Docker) -> String{
     message
 }
 
+/// Env variable names containing one of these words are shown with masked value.
+const SECRET_WORDS: [&str; 7] = ["PASS", "SECRET", "TOKEN", "KEY", "CREDENTIAL", "AUTH", "PRIVATE"];
+
+fn mask_env(variable: &str) -> String {
+    match variable.split_once('=') {
+        Some((key, value)) if !value.is_empty() && SECRET_WORDS.iter().any(|word| key.to_uppercase().contains(word)) => format!("{}=***", key),
+        _ => variable.to_string(),
+    }
+}
+
+/// Container details from `inspect_container`. Docker resolves exact container name first,
+/// then unique ID prefix, so `web` never matches `webhook`.
 async fn get_container_details(docker: &Docker, name: String) -> String {
-    let mut filters = HashMap::new();
-    filters.insert("name".to_string(), vec![name]);
-    let option = bollard::container::ListContainersOptions::<String> {
-        all: true,
-        filters,
-        ..Default::default()
+    let container = match docker.inspect_container(&name, None::<InspectContainerOptions>).await {
+        Ok(container) => container,
+        Err(x) => return audit::failure(format!("Failed with err: {x}")),
     };
-
-    let containers = docker.list_containers(Some(option)).await.unwrap();
+    let config = container.config.unwrap_or_default();
+    let host_config = container.host_config.unwrap_or_default();
+    let state = container.state.unwrap_or_default();
     let mut message = String::new();
 
-    for container in containers {
-        message += &format!("Id: {}\n", container.id.as_ref().unwrap_or(&"N/A".to_string()));
-        message += &format!("Names: {:?}\n", container.names.as_ref().unwrap_or(&Vec::new()));
-        message += &format!("Image: {}\n", container.image.as_ref().unwrap_or(&"N/A".to_string()));
-
-        message += "Ports:\n";
-        if let Some(ports) = &container.ports {
-            for port in ports {
-                message += &format!(
-                    "  IP: {}\n  Private: {}\n  Public: {}\n  Type: {}\n",
-                    port.ip.as_ref().unwrap_or(&"N/A".to_string()),
-                    port.private_port,
-                    port.public_port.as_ref().unwrap_or(&0),
-                    port.typ.as_ref().unwrap()
-                );
-            }
-        } else {
-            message += "  N/A\n";
+    message += &format!("Id: {}\n", container.id.as_ref().unwrap_or(&"N/A".to_string()));
+    message += &format!("Name: {}\n", container.name.as_deref().unwrap_or("N/A").trim_start_matches('/'));
+    message += &format!("Image: {} ({})\n", config.image.as_ref().unwrap_or(&"N/A".to_string()), container.image.as_ref().unwrap_or(&"N/A".to_string()));
+    message += &format!("Created: {}\n", container.created.as_ref().unwrap_or(&"N/A".to_string()));
+    message += &format!("State: {}\n", state.status.map(|status| status.to_string()).unwrap_or("N/A".to_string()));
+    message += &format!("Started: {}\n", state.started_at.as_ref().unwrap_or(&"N/A".to_string()));
+    message += &format!("Finished: {}\n", state.finished_at.as_ref().unwrap_or(&"N/A".to_string()));
+    message += &format!("Exit code: {}\n", state.exit_code.unwrap_or_default());
+    if state.oom_killed.unwrap_or(false) {
+        message += "OOM killed: true\n";
+    }
+    if let Some(error) = state.error.as_ref().filter(|error| !error.is_empty()) {
+        message += &format!("Error: {}\n", error);
+    }
+    message += &format!("Restart count: {}\n", container.restart_count.unwrap_or_default());
+    match &host_config.restart_policy {
+        Some(policy) => message += &format!(
+            "Restart policy: {} (max retries {})\n",
+            policy.name.map(|name| name.to_string()).unwrap_or("N/A".to_string()),
+            policy.maximum_retry_count.unwrap_or_default()
+        ),
+        None => message += "Restart policy: N/A\n",
+    }
+    message += &format!("Entrypoint: {:?}\n", config.entrypoint.unwrap_or_default());
+    message += &format!("Command: {:?}\n", config.cmd.unwrap_or_default());
+
+    message += "Health:\n";
+    if let Some(health) = &state.health {
+        message += &format!("  Status: {}\n", health.status.map(|status| status.to_string()).unwrap_or("N/A".to_string()));
+        message += &format!("  Failing streak: {}\n", health.failing_streak.unwrap_or_default());
+        if let Some(probe) = health.log.as_ref().and_then(|log| log.last()) {
+            message += &format!(
+                "  Last probe: {} (exit code {})\n  Output: {}\n",
+                probe.end.as_ref().unwrap_or(&"N/A".to_string()),
+                probe.exit_code.unwrap_or_default(),
+                probe.output.as_deref().unwrap_or("N/A").trim()
+            );
         }
+    } else {
+        message += "  N/A\n";
+    }
+
+    message += "Resources:\n";
+    message += &format!("  Memory limit: {}\n", host_config.memory.filter(|memory| *memory > 0).map(format_size).unwrap_or("unlimited".to_string()));
+    message += &format!("  CPUs: {}\n", host_config.nano_cpus.filter(|cpus| *cpus > 0).map(|cpus| format!("{:.2}", cpus as f64 / 1e9)).unwrap_or("unlimited".to_string()));
+    message += &format!("  CPU shares: {}\n", host_config.cpu_shares.unwrap_or_default());
+    message += &format!("  PIDs limit: {}\n", host_config.pids_limit.filter(|pids| *pids > 0).map(|pids| pids.to_string()).unwrap_or("unlimited".to_string()));
 
-        message += "Labels:\n";
-        if let Some(labels) = &container.labels {
-            for (label1, label2) in labels {
-                message += &format!("  {}: {}\n", label1, label2);
+    message += "Env:\n";
+    match &config.env {
+        Some(env) if !env.is_empty() => {
+            for variable in env {
+                message += &format!("  {}\n", mask_env(variable));
             }
+        },
+        _ => message += "  N/A\n",
+    }
+
+    message += "Ports:\n";
+    match container.network_settings.as_ref().and_then(|settings| settings.ports.as_ref()) {
+        Some(ports) if !ports.is_empty() => {
+            for (port, bindings) in ports {
+                let bindings: Vec<String> = bindings.iter().flatten()
+                    .map(|binding| format!("{}:{}", binding.host_ip.as_deref().unwrap_or(""), binding.host_port.as_deref().unwrap_or("")))
+                    .collect();
+                message += &format!("  {} -> {}\n", port, if bindings.is_empty() { "not published".to_string() } else { bindings.join(", ") });
+            }
+        },
+        _ => message += "  N/A\n",
+    }
+
+    message += "Labels:\n";
+    if let Some(labels) = &config.labels {
+        for (label1, label2) in labels {
+            message += &format!("  {}: {}\n", label1, label2);
+        }
+    } else {
+        message += "  N/A\n";
+    }
+    message += "Networks:\n";
+    if let Some(networks) = container.network_settings.as_ref().and_then(|settings| settings.networks.as_ref()) {
+        for (network, endpoint) in networks {
+            message += &format!(
+                "  Network: {} ({})\n  IP: {} ({})\n  Gateway: {}\n  DNS: {:?}\n  Links: {:?}\n",
+                network,
+                endpoint.network_id.as_ref().unwrap_or(&"N/A".to_string()),
+                endpoint.ip_address.as_ref().unwrap_or(&"N/A".to_string()),
+                endpoint.ip_prefix_len.as_ref().unwrap_or(&-1),
+                endpoint.gateway.as_ref().unwrap_or(&"N/A".to_string()),
+                endpoint.dns_names.as_ref().unwrap_or(&Vec::new()),
+                endpoint.links.as_ref().unwrap_or(&Vec::new())
+            );
+        }
+    } else {
+        message += "  N/A\n";
+    }
+    message += "Mounts:\n";
+    if let Some(mounts) = &container.mounts {
+        for mount in mounts {
+            message += &format!(
+                "  Type: {}\n  Name: {}\n  Source: {}\n  Destination: {}\n  Driver: {}\n  Mode: {}\n  Read-Write: {}\n  Propagation: {}\n",
+                mount.typ.map(|typ| typ.to_string()).unwrap_or("N/A".to_string()),
+                mount.name.as_ref().unwrap_or(&"N/A".to_string()),
+                mount.source.as_ref().unwrap_or(&"N/A".to_string()),
+                mount.destination.as_ref().unwrap_or(&"N/A".to_string()),
+                mount.driver.as_ref().unwrap_or(&"N/A".to_string()),
+                mount.mode.as_ref().unwrap_or(&"N/A".to_string()),
+                mount.rw.as_ref().unwrap_or(&false),
+                mount.propagation.as_ref().unwrap_or(&"N/A".to_string())
+            );
+        }
+    } else {
+        message += "  N/A\n";
+    }
+
+    message
+}
+
+
+/// Resource usage of one or all running containers, sorted by `cpu` (default) or `mem`.
+async fn container_stats(docker: &Docker, args: &[&str]) -> String {
+    let mut sort = "cpu";
+    let mut name = None;
+    for arg in args.iter().filter(|arg| !arg.is_empty()) {
+        if *arg == "cpu" || *arg == "mem" {
+            sort = arg;
         } else {
-            message += "  N/A\n";
+            name = Some(arg.to_string());
+        }
+    }
+    let names = match name {
+        Some(name) => vec![name],
+        None => match docker.list_containers::<String>(None).await {
+            Ok(containers) => containers.into_iter()
+                .filter_map(|container| container.names?.first().map(|name| name.trim_start_matches('/').to_string()))
+                .collect(),
+            Err(x) => return audit::failure(format!("Failed with err: {x}")),
+        },
+    };
+    if names.is_empty() {
+        return "No running containers".to_string();
+    }
+
+    // Without one-shot docker samples CPU twice, so usage can be calculated from the difference
+    let options = StatsOptions {
+        stream: false,
+        one_shot: false,
+    };
+    let requests = names.iter().map(|name| async move {
+        match docker.stats(name, Some(options)).next().await {
+            Some(Ok(stats)) => Ok(stats),
+            Some(Err(x)) => Err(format!("{}: failed with err: {x}", name)),
+            None => Err(format!("{}: no stats", name)),
         }
-        message += "Networks:\n";
-        if let Some(network_settings) = &container.network_settings {
-            if let Some(networks) = &network_settings.networks {
-                for (network, endpoint) in networks {
-                    message += &format!(
-                        "  Network: {} ({})\n  IP: {} ({})\n  Gateway: {}\n  DNS: {:?}\n  Links: {:?}\n",
-                        network,
-                        endpoint.network_id.as_ref().unwrap_or(&"N/A".to_string()),
-                        endpoint.ip_address.as_ref().unwrap_or(&"N/A".to_string()),
-                        endpoint.ip_prefix_len.as_ref().unwrap_or(&-1),
-                        endpoint.gateway.as_ref().unwrap_or(&"N/A".to_string()),
-                        endpoint.dns_names.as_ref().unwrap_or(&Vec::new()),
-                        endpoint.links.as_ref().unwrap_or(&Vec::new())
-                    );
+    });
+    let mut stats = Vec::new();
+    let mut message = String::new();
+    for result in futures_util::future::join_all(requests).await {
+        match result {
+            Ok(data) => stats.push(data),
+            Err(x) => message += format!("{}\n", audit::failure(x)).as_str(),
+        }
+    }
+    if sort == "mem" {
+        stats.sort_by_key(|data| std::cmp::Reverse(memory_usage(data)));
+    } else {
+        stats.sort_by(|a, b| cpu_percent(b).total_cmp(&cpu_percent(a)));
+    }
+
+    for data in &stats {
+        let limit = data.memory_stats.limit.unwrap_or_default();
+        let memory = memory_usage(data);
+        let (rx, tx) = data.networks.as_ref()
+            .map(|networks| networks.values().fold((0, 0), |(rx, tx), network| (rx + network.rx_bytes, tx + network.tx_bytes)))
+            .unwrap_or_default();
+        let (read, write) = data.blkio_stats.io_service_bytes_recursive.as_ref()
+            .map(|entries| entries.iter().fold((0, 0), |(read, write), entry| match entry.op.to_lowercase().as_str() {
+                "read" => (read + entry.value, write),
+                "write" => (read, write + entry.value),
+                _ => (read, write),
+            }))
+            .unwrap_or_default();
+        message += format!("{}:\n  CPU: {:.2}%\n  Memory: {} / {} ({:.1}%)\n  Net I/O: {} / {}\n  Block I/O: {} / {}\n  PIDs: {}\n",
+            data.name.trim_start_matches('/'),
+            cpu_percent(data),
+            format_size(memory as i64),
+            format_size(limit as i64),
+            if limit > 0 { memory as f64 / limit as f64 * 100.0 } else { 0.0 },
+            format_size(rx as i64),
+            format_size(tx as i64),
+            format_size(read as i64),
+            format_size(write as i64),
+            data.pids_stats.current.unwrap_or_default()
+        ).as_str();
+    }
+    message
+}
+
+/// CPU usage in percent of one core, calculated the same way as `docker stats` does.
+fn cpu_percent(data: &Stats) -> f64 {
+    let cpu_delta = data.cpu_stats.cpu_usage.total_usage as f64 - data.precpu_stats.cpu_usage.total_usage as f64;
+    let system_delta = data.cpu_stats.system_cpu_usage.unwrap_or_default() as f64 - data.precpu_stats.system_cpu_usage.unwrap_or_default() as f64;
+    let cpus = data.cpu_stats.online_cpus
+        .or(data.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|usage| usage.len() as u64))
+        .unwrap_or(1) as f64;
+    if cpu_delta > 0.0 && system_delta > 0.0 {
+        cpu_delta / system_delta * cpus * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Memory usage without page cache, as shown by `docker stats`.
+fn memory_usage(data: &Stats) -> u64 {
+    let cache = match data.memory_stats.stats {
+        Some(MemoryStatsStats::V1(stats)) => stats.total_inactive_file,
+        Some(MemoryStatsStats::V2(stats)) => stats.inactive_file,
+        None => 0,
+    };
+    data.memory_stats.usage.unwrap_or_default().saturating_sub(cache)
+}
+
+/// Container logs. Options are `tail N` (100 lines by default, `all` for every line),
+/// `since 10m` and `grep pattern` that takes the rest of arguments.
+async fn container_logs(docker: &Docker, name: &str, args: &[&str]) -> Result<String, String> {
+    let mut options = LogsOptions::<String> {
+        stdout: true,
+        stderr: true,
+        tail: "100".to_string(),
+        ..Default::default()
+    };
+    let mut pattern = None;
+    let mut args = args.iter().filter(|arg| !arg.is_empty());
+    while let Some(arg) = args.next() {
+        match *arg {
+            "tail" => {
+                let tail = args.next().ok_or("Please provide number of lines after tail")?;
+                if *tail != "all" && tail.parse::<u64>().is_err() {
+                    return Err(format!("Invalid number of lines {}", tail));
                 }
-            } else {
-                message += "  N/A\n";
-            }
-        } else {
-            message += "  N/A\n";
+                options.tail = tail.to_string();
+            },
+            "since" => {
+                let since = args.next().ok_or("Please provide duration after since, e.g. 10m")?;
+                let seconds = parse_duration(since).ok_or(format!("Invalid duration {}", since))?;
+                // Durations before the epoch show every line
+                options.since = i64::try_from(seconds).map_or(0, |seconds| chrono::Local::now().timestamp().saturating_sub(seconds).max(0));
+            },
+            "grep" => {
+                let rest: Vec<&str> = args.by_ref().copied().collect();
+                if rest.is_empty() {
+                    return Err("Please provide pattern after grep".to_string());
+                }
+                pattern = Some(rest.join(" "));
+            },
+            _ => return Err(format!("Unknown option {}", arg)),
         }
-        message += "Mounts:\n";
-        if let Some(mounts) = &container.mounts {
-            for mount in mounts {
-                message += &format!(
-                    "  Type: {}\n  Name: {}\n  Source: {}\n  Destination: {}\n  Driver: {}\n  Mode: {}\n  Read-Write: {}\n  Propagation: {}\n",
-                    mount.typ.as_ref().unwrap(),
-                    mount.name.as_ref().unwrap_or(&"N/A".to_string()),
-                    mount.source.as_ref().unwrap_or(&"N/A".to_string()),
-                    mount.destination.as_ref().unwrap_or(&"N/A".to_string()),
-                    mount.driver.as_ref().unwrap_or(&"N/A".to_string()),
-                    mount.mode.as_ref().unwrap_or(&"N/A".to_string()),
-                    mount.rw.as_ref().unwrap_or(&false),
-                    mount.propagation.as_ref().unwrap_or(&"N/A".to_string())
-                );
+    }
+
+    let mut logs = String::new();
+    let mut stream = docker.logs(name, Some(options));
+    while let Some(output) = stream.next().await {
+        match output {
+            Ok(output) => logs += output.to_string().as_str(),
+            Err(x) => return Err(format!("Failed with err: {x}")),
+        }
+    }
+    if let Some(pattern) = pattern {
+        logs = logs.lines().filter(|line| line.contains(&pattern)).map(|line| format!("{}\n", line)).collect();
+    }
+    Ok(logs)
+}
+
+/// Run command inside container, returns exit code followed by stdout and stderr.
+async fn exec_container(docker: &Docker, name: &str, command: Vec<&str>, timeout: u64) -> Result<String, String> {
+    let options = CreateExecOptions {
+        cmd: Some(command),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        ..Default::default()
+    };
+    let exec = docker.create_exec(name, options).await.map_err(|x| format!("Failed with err: {x}"))?;
+    let mut output = String::new();
+    if let StartExecResults::Attached { output: mut stream, .. } = docker.start_exec(&exec.id, None).await.map_err(|x| format!("Failed with err: {x}"))? {
+        let collect = async {
+            while let Some(Ok(chunk)) = stream.next().await {
+                output += chunk.to_string().as_str();
             }
-        } else {
-            message += "  N/A\n";
+        };
+        if tokio::time::timeout(Duration::from_secs(timeout), collect).await.is_err() {
+            output += format!("\nOutput truncated after {}s timeout, command is still running", timeout).as_str();
         }
-        message += "\n\n";
     }
+    let exit_code = match docker.inspect_exec(&exec.id).await {
+        Ok(inspect) => inspect.exit_code.map(|code| code.to_string()).unwrap_or("N/A".to_string()),
+        Err(x) => format!("N/A ({x})"),
+    };
+    Ok(format!("Exit code: {}\n{}", exit_code, output))
+}
 
-    message
+/// Send output as message, or as document when it doesn't fit into one message.
+async fn send_output(bot: &Bot, chat: ChatId, output: String, file_name: &str) -> ResponseResult<()> {
+    if output.trim().is_empty() {
+        bot.send_message(chat, "No output").await?;
+    } else if output.chars().count() <= MESSAGE_LIMIT {
+        bot.send_message(chat, output).await?;
+    } else {
+        bot.send_document(chat, InputFile::memory(output.into_bytes()).file_name(file_name.to_string())).await?;
+    }
+    Ok(())
 }
 
+/// Split image reference into repository and tag, `latest` is used when tag is omitted.
+fn image_tag(image: &str) -> (&str, &str) {
+    match image.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+        _ => (image, "latest"),
+    }
+}
+
+/// Repository digest of image, or image id for images that were built locally.
+async fn image_digest(docker: &Docker, image: &str) -> Result<String, String> {
+    let inspect = docker.inspect_image(image).await.map_err(|x| format!("Failed with err: {x}"))?;
+    Ok(inspect.repo_digests.and_then(|digests| digests.into_iter().next())
+        .or(inspect.id)
+        .unwrap_or("N/A".to_string()))
+}
+
+/// Pull image tag of the container and recreate it when image changed, like Watchtower does.
+/// New container gets config, host config (mounts, ports, restart policy), volumes and networks of the old one.
+async fn update_container(docker: &Docker, name: &str) -> Result<String, String> {
+    let info = docker.inspect_container(name, None::<InspectContainerOptions>).await.map_err(|x| format!("Failed with err: {x}"))?;
+    let config = info.config.clone().ok_or("Container has no config")?;
+    let image = config.image.clone().ok_or("Container has no image")?;
+    if image.contains('@') || image.starts_with("sha256:") {
+        return Err(format!("Image {} is pinned to digest and can't be updated", image));
+    }
+    let old_image = info.image.clone().unwrap_or_default();
+    let old_digest = image_digest(docker, &old_image).await?;
+
+    let (repository, tag) = image_tag(&image);
+    let options = CreateImageOptions {
+        from_image: repository,
+        tag,
+        ..Default::default()
+    };
+    let mut pull = docker.create_image(Some(options), None, None);
+    while let Some(result) = pull.next().await {
+        result.map_err(|x| format!("Failed to pull {} with err: {x}", image))?;
+    }
+    let new_image = docker.inspect_image(&image).await.map_err(|x| format!("Failed with err: {x}"))?.id.unwrap_or_default();
+    if new_image == old_image {
+        return Ok(format!("{} is up to date\nDigest: {}", name, old_digest));
+    }
+    let new_digest = image_digest(docker, &new_image).await?;
+
+    recreate_container(docker, info, config.into()).await?;
+    Ok(format!("Updated {} ({})\nOld: {}\nNew: {}", name, image, old_digest, new_digest))
+}
+
+/// Binds of volumes mounted without entry in host config, e.g. anonymous volumes declared by image.
+/// They are attached by name, otherwise new container would get new empty volumes.
+fn volume_binds(mounts: &[MountPoint], host_config: &HostConfig) -> Vec<String> {
+    let mut targets: Vec<&str> = host_config.binds.iter().flatten()
+        .filter_map(|bind| bind.split(':').nth(1))
+        .collect();
+    targets.extend(host_config.mounts.iter().flatten().filter_map(|mount| mount.target.as_deref()));
+    mounts.iter()
+        .filter(|mount| mount.typ == Some(MountPointTypeEnum::VOLUME))
+        .filter_map(|mount| Some((mount.name.as_deref()?, mount.destination.as_deref()?, mount.rw == Some(false))))
+        .filter(|(_, destination, _)| !targets.contains(destination))
+        .map(|(name, destination, read_only)| format!("{}:{}{}", name, destination, if read_only { ":ro" } else { "" }))
+        .collect()
+}
+
+/// Replace container with new one created from `config`. Old container is renamed and kept
+/// until the new one is created and started, so it's restored when anything fails.
+async fn recreate_container(docker: &Docker, info: ContainerInspectResponse, mut config: Config<String>) -> Result<(), String> {
+    let id = info.id.unwrap_or_default();
+    let name = info.name.unwrap_or_default().trim_start_matches('/').to_string();
+    let running = info.state.and_then(|state| state.running).unwrap_or(false);
+    let mut host_config = info.host_config.unwrap_or_default();
+    let network_mode = host_config.network_mode.clone().unwrap_or_default();
+    let volumes = volume_binds(info.mounts.as_deref().unwrap_or_default(), &host_config);
+    if !volumes.is_empty() {
+        host_config.binds.get_or_insert_with(Vec::new).extend(volumes);
+    }
+
+    // Hostname defaults to short container id and must not be set for host and container network modes
+    if config.hostname.as_ref().is_some_and(|hostname| id.starts_with(hostname.as_str()))
+        || network_mode == "host" || network_mode.starts_with("container:") {
+        config.hostname = None;
+    }
+    // Only one network can be set on create, others are connected after it
+    let mut networks: Vec<(String, EndpointSettings)> = info.network_settings
+        .and_then(|settings| settings.networks)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(network, endpoint)| (network, EndpointSettings {
+            aliases: endpoint.aliases.map(|aliases| aliases.into_iter().filter(|alias| !id.starts_with(alias.as_str())).collect()),
+            links: endpoint.links,
+            ipam_config: endpoint.ipam_config,
+            driver_opts: endpoint.driver_opts,
+            ..Default::default()
+        }))
+        .collect();
+    networks.sort_by_key(|(network, _)| *network != network_mode);
+    let mut networks = networks.into_iter();
+    if !network_mode.starts_with("container:") {
+        config.networking_config = networks.next().map(|(network, endpoint)| NetworkingConfig {
+            endpoints_config: HashMap::from([(network, endpoint)]),
+        });
+    }
+    config.host_config = Some(host_config);
+
+    let old_name = format!("{}-old-{}", name, id.chars().take(12).collect::<String>());
+    if running {
+        docker.stop_container(&id, Some(StopContainerOptions { t: 30 })).await.map_err(|x| format!("Failed to stop with err: {x}"))?;
+    }
+    docker.rename_container(&id, RenameContainerOptions { name: old_name.as_str() }).await.map_err(|x| format!("Failed to rename with err: {x}"))?;
+
+    let create = async {
+        let created = docker.create_container(Some(CreateContainerOptions { name: name.clone(), platform: None }), config).await?;
+        for (network, endpoint) in networks {
+            docker.connect_network(&network, ConnectNetworkOptions { container: created.id.clone(), endpoint_config: endpoint }).await?;
+        }
+        if running {
+            docker.start_container(&created.id, None::<StartContainerOptions<String>>).await?;
+        }
+        Ok::<String, bollard::errors::Error>(created.id)
+    };
+    match create.await {
+        Ok(_) => {
+            let options = RemoveContainerOptions { force: true, ..Default::default() };
+            docker.remove_container(&id, Some(options)).await.map_err(|x| format!("Updated, but failed to remove old container {} with err: {x}", old_name))
+        },
+        Err(x) => {
+            let options = RemoveContainerOptions { force: true, ..Default::default() };
+            let _ = docker.remove_container(&name, Some(options)).await;
+            let _ = docker.rename_container(&id, RenameContainerOptions { name: name.as_str() }).await;
+            if running {
+                let _ = docker.start_container(&id, None::<StartContainerOptions<String>>).await;
+            }
+            Err(format!("Failed to recreate container with err: {x}\nOld container was restored"))
+        },
+    }
+}
+
+/// Dry run of `container update`: compares local image digests of containers with
+/// digests in registry without pulling anything.
+async fn check_updates(docker: &Docker) -> String {
+    let containers = match docker.list_containers::<String>(None).await {
+        Ok(containers) => containers,
+        Err(x) => return audit::failure(format!("Failed with err: {x}")),
+    };
+    let checks = containers.iter().map(|container| async move {
+        let name = container_name(container);
+        let image = container.image.clone().unwrap_or_default();
+        if image.contains('@') || image.starts_with("sha256:") {
+            return format!("{} ({}): pinned to digest\n", name, image);
+        }
+        let local = match docker.inspect_image(&image).await {
+            Ok(inspect) => inspect.repo_digests.unwrap_or_default(),
+            Err(x) => return audit::failure(format!("{} ({}): failed with err: {x}\n", name, image)),
+        };
+        if local.is_empty() {
+            return format!("{} ({}): local image\n", name, image);
+        }
+        match docker.inspect_registry_image(&image, None).await {
+            Ok(remote) => match remote.descriptor.digest {
+                Some(digest) if local.iter().any(|local| local.ends_with(&format!("@{}", digest))) => String::new(),
+                Some(digest) => format!("{} ({}): update available\n  Local: {}\n  Registry: {}\n", name, image, local.join(", "), digest),
+                None => format!("{} ({}): registry returned no digest\n", name, image),
+            },
+            Err(x) => audit::failure(format!("{} ({}): failed with err: {x}\n", name, image)),
+        }
+    });
+    let message: String = futures_util::future::join_all(checks).await.concat();
+    if message.is_empty() {
+        "All containers are up to date".to_string()
+    } else {
+        message
+    }
+}
 
 async fn stop_container(docker: &Docker, name: String) -> String{
     let options = Some(StopContainerOptions{
@@ -236,28 +870,28 @@ async fn stop_container(docker: &Docker, name: String) -> String{
     });
     match docker.stop_container(name.as_str(), options).await{
         Ok(_) => "Stoped successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
 async fn start_container(docker: &Docker, name: String) -> String{
     match docker.start_container(name.as_str(), None::<StartContainerOptions<String>>).await{
         Ok(_) => "Started successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
 async fn pause_container(docker: &Docker, name: String) -> String{
     match docker.pause_container(name.as_str()).await{
         Ok(_) => "Paused successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
 async fn unpause_container(docker: &Docker, name: String) -> String{
     match docker.unpause_container(name.as_str()).await{
         Ok(_) => "Unpaused successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
@@ -267,7 +901,7 @@ async fn kill_container(docker: &Docker, name: String) -> String{
     });
     match docker.kill_container(name.as_str(), options).await{
         Ok(_) => "Killed successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
@@ -277,7 +911,7 @@ async fn restart_container(docker: &Docker, name: String) -> String{
     });
     match docker.restart_container(name.as_str(), options).await{
         Ok(_) => "Restarted successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
@@ -287,7 +921,7 @@ async fn rename_container(docker: &Docker, name: String, new_name: String) -> St
     };
     match docker.rename_container(name.as_str(), required).await{
         Ok(_) => "Renamed successfully".to_string(),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
@@ -297,11 +931,175 @@ async fn prune_container(docker: &Docker) -> String {
     };
     match docker.prune_containers(Some(options)).await{
         Ok(data) => format!("Pruned successfully\nRemoved: {:?}\nFree up space: {}", data.containers_deleted.unwrap_or_default(), data.space_reclaimed.unwrap_or_default()),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
+    }
+}
+
+async fn compose_command_handler(bot: &Bot, msg: &Message, com: &[&str], docker: &Docker, compose: &ComposeConfig, docker_host: Option<&str>) -> ResponseResult<()>{
+    if com.len() < 2 || com[1] == "list" || com[1].is_empty() {
+        for part in split_message(&list_projects(docker).await) {
+            bot.send_message(msg.chat.id, part).await?;
+        }
+    } else if com.len() < 3 || com[2].is_empty() {
+        bot.send_message(msg.chat.id, "Please provide project name").await?;
+    } else if com[1] == "ps" {
+        for part in split_message(&project_ps(docker, com[2]).await) {
+            bot.send_message(msg.chat.id, part).await?;
+        }
+    } else if com[1] == "stop" || com[1] == "start" || com[1] == "restart" {
+        bot.send_message(msg.chat.id, project_action(docker, com[2], com[1]).await).await?;
+    } else if com[1] == "up" || com[1] == "pull" {
+        if let Some(file) = compose.projects.get(com[2]) {
+            bot.send_message(msg.chat.id, format!("Running docker compose {} for {}", com[1], com[2])).await?;
+            let output = run_compose(file, com[2], com[1], compose.timeout, docker_host).await;
+            send_output(bot, msg.chat.id, output, &format!("{}-{}.log", com[2], com[1])).await?;
+        } else {
+            bot.send_message(msg.chat.id, format!("Compose file of {} is not set in docker.compose config", com[2])).await?;
+        }
+    } else {
+        bot.send_message(msg.chat.id, get_docker_command_help_text()).await?;
+    }
+    Ok(())
+}
+
+/// Containers of compose project, or all containers created by compose when project is `None`.
+async fn compose_containers(docker: &Docker, project: Option<&str>) -> Result<Vec<ContainerSummary>, String> {
+    let mut filters = HashMap::new();
+    let label = match project {
+        Some(project) => format!("{}={}", PROJECT_LABEL, project),
+        None => PROJECT_LABEL.to_string(),
+    };
+    filters.insert("label".to_string(), vec![label]);
+    let options = bollard::container::ListContainersOptions::<String> {
+        all: true,
+        filters,
+        ..Default::default()
+    };
+    docker.list_containers(Some(options)).await.map_err(|x| format!("Failed with err: {x}"))
+}
+
+fn container_label<'a>(container: &'a ContainerSummary, label: &str) -> &'a str {
+    container.labels.as_ref().and_then(|labels| labels.get(label)).map(|value| value.as_str()).unwrap_or("N/A")
+}
+
+fn container_name(container: &ContainerSummary) -> String {
+    container.names.as_ref()
+        .and_then(|names| names.first())
+        .map(|name| name.trim_start_matches('/').to_string())
+        .or(container.id.clone())
+        .unwrap_or_default()
+}
+
+async fn list_projects(docker: &Docker) -> String {
+    match compose_containers(docker, None).await {
+        Ok(containers) => format_projects(&containers),
+        Err(x) => audit::failure(x),
+    }
+}
+
+/// Compose projects of containers grouped by project label, with running containers and services.
+fn format_projects(containers: &[ContainerSummary]) -> String {
+    if containers.is_empty() {
+        return "No compose projects".to_string();
+    }
+    let mut projects: BTreeMap<&str, Vec<&ContainerSummary>> = BTreeMap::new();
+    for container in containers {
+        projects.entry(container_label(container, PROJECT_LABEL)).or_default().push(container);
+    }
+
+    let mut message = String::new();
+    for (project, containers) in projects {
+        let running = containers.iter().filter(|container| container.state.as_deref() == Some("running")).count();
+        let mut services: Vec<&str> = containers.iter().map(|container| container_label(container, SERVICE_LABEL)).collect();
+        services.sort();
+        services.dedup();
+        message += format!("{}: {}/{} running\n  Services: {}\n", project, running, containers.len(), services.join(", ")).as_str();
     }
+    message
+}
+
+async fn project_ps(docker: &Docker, project: &str) -> String {
+    match compose_containers(docker, Some(project)).await {
+        Ok(containers) => format_project_ps(project, containers),
+        Err(x) => audit::failure(x),
+    }
+}
+
+/// Containers of compose project sorted by service.
+fn format_project_ps(project: &str, mut containers: Vec<ContainerSummary>) -> String {
+    if containers.is_empty() {
+        return format!("Project {} not found", project);
+    }
+    containers.sort_by(|a, b| container_label(a, SERVICE_LABEL).cmp(container_label(b, SERVICE_LABEL)));
+
+    let mut message = String::new();
+    for container in &containers {
+        message += format!("Service: {}\nName: {}\nImage: {}\nState: {}\nStatus: {}\n\n",
+            container_label(container, SERVICE_LABEL),
+            container_name(container),
+            container.image.as_deref().unwrap_or("N/A"),
+            container.state.as_deref().unwrap_or("N/A"),
+            container.status.as_deref().unwrap_or("N/A")
+        ).as_str();
+    }
+    message
+}
+
+/// Stop, start or restart every container of compose project.
+async fn project_action(docker: &Docker, project: &str, action: &str) -> String {
+    let containers = match compose_containers(docker, Some(project)).await {
+        Ok(containers) => containers,
+        Err(x) => return audit::failure(x),
+    };
+    if containers.is_empty() {
+        return format!("Project {} not found", project);
+    }
+
+    let mut message = String::new();
+    for container in &containers {
+        let name = container_name(container);
+        let result = match action {
+            "stop" => stop_container(docker, name.clone()).await,
+            "start" => start_container(docker, name.clone()).await,
+            _ => restart_container(docker, name.clone()).await,
+        };
+        message += format!("{}: {}\n", name, result).as_str();
+    }
+    message
 }
 
-async fn image_command_handler(bot: &Bot, msg: &Message, com: &Vec<&str>, docker: &Docker)-> ResponseResult<()>{
+/// Run `docker compose up -d` or `docker compose pull` for compose file, returns exit status and output.
+/// Requires docker CLI with compose plugin installed next to the bot.
+async fn run_compose(file: &str, project: &str, action: &str, timeout: u64, docker_host: Option<&str>) -> String {
+    let mut command = Process::new("docker");
+    command.args(["compose", "-f", file, "-p", project, action]);
+    if action == "up" {
+        command.arg("-d");
+    }
+    if let Some(host) = docker_host {
+        // docker CLI expects tcp:// scheme for remote daemon
+        command.env("DOCKER_HOST", host.replacen("http://", "tcp://", 1));
+    }
+    let child = command.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(x) => return audit::failure(format!("Failed with err: {x}")),
+    };
+    match tokio::time::timeout(Duration::from_secs(timeout), child.wait_with_output()).await {
+        Ok(Ok(output)) => format!("Exit status: {}\n{}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)),
+        Ok(Err(x)) => audit::failure(format!("Failed with err: {x}")),
+        Err(_) => format!("docker compose {} was killed after {}s timeout", action, timeout),
+    }
+}
+
+async fn image_command_handler(bot: &Bot, msg: &Message, com: &[&str], docker: &Docker)-> ResponseResult<()>{
     if com.len() >= 2{
         if com[1] == "list"{
             bot.send_message(msg.chat.id, list_images(docker).await).await?;
@@ -331,9 +1129,9 @@ async fn list_images(docker: &Docker) -> String {
                 image.shared_size,
                 image.containers).as_str();
             }
-            return message;
+            message
         },
-        Err(x) => format!("Failed with err: {x}").to_string()
+        Err(x) => audit::failure(format!("Failed with err: {x}")).to_string()
       }
 }
 
@@ -343,11 +1141,11 @@ async fn prune_images(docker: &Docker) -> String{
     };
     match docker.prune_images(Some(options)).await{
         Ok(data) => format!("Pruned successfully\nRemoved: {:?}\nFree up space: {}", data.images_deleted.unwrap_or_default(), data.space_reclaimed.unwrap_or_default()),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
-async fn network_command_handler(bot: &Bot, msg: &Message, com: &Vec<&str>, docker: &Docker)-> ResponseResult<()>{
+async fn network_command_handler(bot: &Bot, msg: &Message, com: &[&str], docker: &Docker)-> ResponseResult<()>{
     if com.len() >= 2{
         if com[1] == "list"{
             bot.send_message(msg.chat.id, list_networks(docker).await).await?;
@@ -419,7 +1217,7 @@ async fn list_networks(docker: &Docker) -> String{
 
             result
         },
-        Err(x) => format!("Failed with err: {x}").to_string()
+        Err(x) => audit::failure(format!("Failed with err: {x}")).to_string()
     }  
 }
 
@@ -429,11 +1227,11 @@ async fn prune_networks(docker: &Docker) -> String{
     };
     match docker.prune_networks(Some(options)).await{
         Ok(data) => format!("Pruned successfully\nRemoved: {:?}", data.networks_deleted.unwrap_or_default()),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
-async fn volumes_command_handler(bot: &Bot, msg: &Message, com: &Vec<&str>, docker: &Docker)-> ResponseResult<()>{
+async fn volumes_command_handler(bot: &Bot, msg: &Message, com: &[&str], docker: &Docker)-> ResponseResult<()>{
     if com.len() >= 2{
         if com[1] == "list"{
             bot.send_message(msg.chat.id, list_volumes(docker).await).await?;
@@ -499,7 +1297,7 @@ async fn list_volumes(docker: &Docker) -> String{
             }
             result
         },
-        Err(x) => format!("Failed with err: {x}").to_string()
+        Err(x) => audit::failure(format!("Failed with err: {x}")).to_string()
     }
 }
 
@@ -509,12 +1307,12 @@ async fn prune_volumes(docker: &Docker) -> String{
     };
     match docker.prune_volumes(Some(options)).await{
         Ok(data) => format!("Pruned successfully\nRemoved: {:?}\nFree up space: {}", data.volumes_deleted.unwrap_or_default(), data.space_reclaimed.unwrap_or_default()),
-        Err(x) => format!("Failed with err: {x}")
+        Err(x) => audit::failure(format!("Failed with err: {x}"))
     }
 }
 
 fn get_docker_command_help_text() -> String {
-    let help_text = r#"
+    r#"
 Docker Command Usage:
 
 /docker [subcommand] [arguments]
@@ -528,7 +1326,9 @@ container [sub-subcommand] [arguments]
   Manages Docker containers.
   Sub-subcommands:
     list or ""                  - Lists all containers
-    detail or det [name]        - Shows details of a container
+    detail or det [name]        - Shows details of a container found by exact name or ID prefix:
+                                  state, restart policy and count, health, resources, env (secrets masked),
+                                  ports, networks and mounts
     stop [name]                 - Stops a container
     start [name]                - Starts a container
     pause [name]                - Pauses a container
@@ -537,6 +1337,29 @@ container [sub-subcommand] [arguments]
     restart [name]              - Restarts a container
     rename [old] [new]          - Renames a container
     prune                       - Removes all stopped containers
+    stats [name] [cpu|mem]      - Shows CPU, memory, network and block I/O usage and PIDs
+                                  of the container or all running containers sorted by CPU or memory
+    exec [name] [command]       - Runs command inside the container and shows its output and exit code.
+                                  Only containers and commands allowed by docker.exec config can be used
+    logs [name] [options]       - Shows last 100 lines of container logs. Options:
+                                  tail [N|all], since [10m|2h|1d], grep [pattern]
+                                  Long logs are sent as .log file
+    update [name]               - Pulls image tag of the container and recreates it with the same
+                                  config, mounts and volumes, ports, networks and restart policy when image changed
+
+updates
+  Lists running containers whose images have newer digest in registry, nothing is pulled.
+
+compose [sub-subcommand] [project]
+  Manages Docker Compose projects, containers are grouped by compose labels.
+  Sub-subcommands:
+    list or ""                  - Lists projects with running containers count and services
+    ps [project]                - Shows services and containers of the project
+    stop [project]              - Stops every container of the project
+    start [project]             - Starts every container of the project
+    restart [project]           - Restarts every container of the project
+    up [project]                - Runs docker compose up -d for compose file from docker.compose config
+    pull [project]              - Runs docker compose pull for compose file from docker.compose config
 
 image [sub-subcommand] [arguments]
   Manages Docker images.
@@ -559,7 +1382,211 @@ volume [sub-subcommand] [arguments]
 If no subcommand is provided or an invalid subcommand is given, the default behavior is to display the Docker information.
 
 Note: Replace [name], [old], and [new] with the actual names/identifiers of the Docker resources you want to manage.
-"#.to_string();
+"#.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec_config(yaml: &str) -> ExecConfig {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn exec_command_must_be_equal_to_allowed() {
+        let exec = exec_config(r#"allow: {web: ["ls", "cat /etc/nginx/nginx.conf"]}"#);
+        assert!(exec.allowed("web", "ls"));
+        assert!(exec.allowed("web", "cat /etc/nginx/nginx.conf"));
+        assert!(!exec.allowed("web", "ls /root"));
+        assert!(!exec.allowed("web", "cat /etc/nginx/nginx.conf /etc/shadow"));
+        assert!(!exec.allowed("db", "ls"));
+    }
+
+    #[test]
+    fn exec_arguments_are_allowed_by_trailing_star() {
+        let exec = exec_config(r#"allow: {web: ["nginx -t *"], "*": ["df -h"]}"#);
+        assert!(exec.allowed("web", "nginx -t"));
+        assert!(exec.allowed("web", "nginx -t -c /etc/nginx/nginx.conf"));
+        assert!(!exec.allowed("web", "nginx -s stop"));
+        assert!(!exec.allowed("web", "nginx -tc"));
+        assert!(exec.allowed("db", "df -h"));
+        assert!(!exec.allowed("db", "df -h /"));
+    }
+
+    fn mount(typ: MountPointTypeEnum, name: Option<&str>, destination: &str, rw: bool) -> MountPoint {
+        MountPoint {
+            typ: Some(typ),
+            name: name.map(|name| name.to_string()),
+            destination: Some(destination.to_string()),
+            rw: Some(rw),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn anonymous_volumes_are_attached_by_name() {
+        let host_config = HostConfig {
+            binds: Some(vec!["/srv/web:/usr/share/nginx/html:ro".to_string(), "logs:/var/log/nginx".to_string()]),
+            mounts: Some(vec![bollard::models::Mount { target: Some("/cache".to_string()), ..Default::default() }]),
+            ..Default::default()
+        };
+        let mounts = [
+            mount(MountPointTypeEnum::BIND, None, "/usr/share/nginx/html", false),
+            mount(MountPointTypeEnum::VOLUME, Some("logs"), "/var/log/nginx", true),
+            mount(MountPointTypeEnum::VOLUME, Some("cache"), "/cache", true),
+            mount(MountPointTypeEnum::VOLUME, Some("3f2a9c"), "/data", true),
+            mount(MountPointTypeEnum::VOLUME, Some("8b1d4e"), "/config", false),
+            mount(MountPointTypeEnum::TMPFS, None, "/tmp", true),
+        ];
+        assert_eq!(volume_binds(&mounts, &host_config), ["3f2a9c:/data", "8b1d4e:/config:ro"]);
+        assert!(volume_binds(&[], &HostConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn image_tag_defaults_to_latest() {
+        assert_eq!(image_tag("nginx"), ("nginx", "latest"));
+        assert_eq!(image_tag("nginx:1.25"), ("nginx", "1.25"));
+        assert_eq!(image_tag("ghcr.io/owner/app:v2"), ("ghcr.io/owner/app", "v2"));
+        assert_eq!(image_tag("registry.local:5000/app"), ("registry.local:5000/app", "latest"));
+        assert_eq!(image_tag("registry.local:5000/app:dev"), ("registry.local:5000/app", "dev"));
+    }
+
+    #[test]
+    fn exec_star_allows_every_command() {
+        let exec = exec_config(r#"{timeout: 5, allow: {web: ["*"]}}"#);
+        assert!(exec.allowed("web", "rm -rf /tmp/cache"));
+        assert!(!exec.allowed("db", "ls"));
+        assert_eq!(exec.timeout, 5);
+    }
+
+    #[test]
+    fn secret_env_values_are_masked() {
+        assert_eq!(mask_env("POSTGRES_PASSWORD=hunter2"), "POSTGRES_PASSWORD=***");
+        assert_eq!(mask_env("api_token=abc=def"), "api_token=***");
+        assert_eq!(mask_env("AWS_SECRET_ACCESS_KEY=x"), "AWS_SECRET_ACCESS_KEY=***");
+        assert_eq!(mask_env("GPG_PRIVATE=key"), "GPG_PRIVATE=***");
+    }
+
+    #[test]
+    fn other_env_values_are_kept() {
+        assert_eq!(mask_env("PATH=/usr/bin:/bin"), "PATH=/usr/bin:/bin");
+        assert_eq!(mask_env("TZ=Europe/Berlin"), "TZ=Europe/Berlin");
+        assert_eq!(mask_env("DB_PASSWORD="), "DB_PASSWORD=");
+        assert_eq!(mask_env("SECRET"), "SECRET");
+    }
+
+    /// Stats sample with cpu counters of current and previous read, `memory` is `stats` of `memory_stats`.
+    fn stats(cpu: (u64, Option<u64>), precpu: (u64, Option<u64>), online_cpus: Option<u64>, usage: Option<u64>, memory: serde_json::Value) -> Stats {
+        let cpu_stats = |(total_usage, system_cpu_usage): (u64, Option<u64>)| serde_json::json!({
+            "cpu_usage": {"total_usage": total_usage, "usage_in_usermode": 0, "usage_in_kernelmode": 0, "percpu_usage": [0, 0]},
+            "system_cpu_usage": system_cpu_usage,
+            "online_cpus": online_cpus,
+            "throttling_data": {"periods": 0, "throttled_periods": 0, "throttled_time": 0}
+        });
+        serde_json::from_value(serde_json::json!({
+            "read": "2024-01-01T00:00:01Z",
+            "preread": "2024-01-01T00:00:00Z",
+            "num_procs": 0,
+            "pids_stats": {},
+            "memory_stats": {"usage": usage, "stats": memory},
+            "blkio_stats": {},
+            "cpu_stats": cpu_stats(cpu),
+            "precpu_stats": cpu_stats(precpu),
+            "storage_stats": {}
+        })).unwrap()
+    }
+
+    fn cpu_stats(cpu: (u64, Option<u64>), precpu: (u64, Option<u64>), online_cpus: Option<u64>) -> Stats {
+        stats(cpu, precpu, online_cpus, None, serde_json::Value::Null)
+    }
+
+    #[test]
+    fn cpu_percent_is_scaled_by_cpus() {
+        assert_eq!(cpu_percent(&cpu_stats((300, Some(2000)), (100, Some(1000)), Some(4))), 80.0);
+        // Number of cpus falls back to the length of per cpu usage
+        assert_eq!(cpu_percent(&cpu_stats((300, Some(2000)), (100, Some(1000)), None)), 40.0);
+    }
+
+    #[test]
+    fn cpu_percent_without_delta_is_zero() {
+        assert_eq!(cpu_percent(&cpu_stats((300, Some(1000)), (100, Some(1000)), Some(4))), 0.0);
+        assert_eq!(cpu_percent(&cpu_stats((300, None), (100, None), Some(4))), 0.0);
+        assert_eq!(cpu_percent(&cpu_stats((100, Some(2000)), (100, Some(1000)), Some(4))), 0.0);
+        // Counters going back after container restart
+        assert_eq!(cpu_percent(&cpu_stats((100, Some(2000)), (300, Some(1000)), Some(4))), 0.0);
+        assert_eq!(cpu_percent(&cpu_stats((300, Some(500)), (100, Some(1000)), Some(4))), 0.0);
+    }
+
+    #[test]
+    fn first_sample_is_compared_with_empty_precpu() {
+        let percent = cpu_percent(&cpu_stats((500, Some(10000)), (0, None), Some(2)));
+        assert!(percent.is_finite());
+        assert_eq!(percent, 10.0);
+    }
+
+    #[test]
+    fn memory_usage_excludes_inactive_files() {
+        let v2 = |inactive_file: u64| {
+            let names = ["anon", "file", "kernel_stack", "slab", "sock", "shmem", "file_mapped", "file_dirty", "file_writeback", "anon_thp",
+                "inactive_anon", "active_anon", "inactive_file", "active_file", "unevictable", "slab_reclaimable", "slab_unreclaimable",
+                "pgfault", "pgmajfault", "workingset_refault", "workingset_activate", "workingset_nodereclaim", "pgrefill", "pgscan",
+                "pgsteal", "pgactivate", "pgdeactivate", "pglazyfree", "pglazyfreed", "thp_fault_alloc", "thp_collapse_alloc"];
+            let mut memory: serde_json::Map<String, serde_json::Value> = names.iter().map(|name| (name.to_string(), 0.into())).collect();
+            memory.insert("inactive_file".to_string(), inactive_file.into());
+            serde_json::Value::Object(memory)
+        };
+        let memory = |usage: Option<u64>, memory: serde_json::Value| memory_usage(&stats((0, None), (0, None), None, usage, memory));
+        assert_eq!(memory(Some(1000), v2(300)), 700);
+        assert_eq!(memory(Some(1000), serde_json::Value::Null), 1000);
+        assert_eq!(memory(Some(100), v2(300)), 0);
+        assert_eq!(memory(None, v2(300)), 0);
+    }
+
+
+    fn compose_container(name: &str, project: &str, service: &str, state: &str) -> ContainerSummary {
+        ContainerSummary {
+            names: Some(vec![format!("/{}", name)]),
+            image: Some(format!("{}:latest", service)),
+            state: Some(state.to_string()),
+            status: Some(format!("{} 5 minutes", state)),
+            labels: Some(HashMap::from([
+                (PROJECT_LABEL.to_string(), project.to_string()),
+                (SERVICE_LABEL.to_string(), service.to_string()),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn containers_are_grouped_by_compose_project() {
+        let containers = [
+            compose_container("blog-web-1", "blog", "web", "running"),
+            compose_container("media-jellyfin-1", "media", "jellyfin", "running"),
+            compose_container("blog-db-1", "blog", "db", "exited"),
+            compose_container("blog-web-2", "blog", "web", "running"),
+        ];
+        assert_eq!(format_projects(&containers), "blog: 2/3 running\n  Services: db, web\nmedia: 1/1 running\n  Services: jellyfin\n");
+        assert_eq!(format_projects(&[]), "No compose projects");
+    }
+
+    #[test]
+    fn project_containers_are_listed_by_service() {
+        let containers = vec![
+            compose_container("blog-web-1", "blog", "web", "running"),
+            compose_container("blog-db-1", "blog", "db", "exited"),
+        ];
+        assert_eq!(format_project_ps("blog", containers),
+            "Service: db\nName: blog-db-1\nImage: db:latest\nState: exited\nStatus: exited 5 minutes\n\n\
+            Service: web\nName: blog-web-1\nImage: web:latest\nState: running\nStatus: running 5 minutes\n\n");
+        assert_eq!(format_project_ps("unknown", Vec::new()), "Project unknown not found");
+    }
+
+    #[test]
+    fn compose_defaults_to_list() {
+        let plugin = DockerPlugin::default();
+        assert_eq!(plugin.action(vec!["compose"]), ["compose", "list"]);
+        assert_eq!(plugin.action(vec!["compose", "ps", "unknown"]), ["compose", "ps", "unknown"]);
+    }
 
-    help_text
 }
@@ -0,0 +1,196 @@
+use teloxide::prelude::*;
+use bollard::{models::EventMessage, system::EventsOptions, Docker};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::utils::format_duration;
+
+/// `docker.events` config section.
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventsConfig {
+    chats: Vec<i64>,
+    /// Only watch containers with these names.
+    #[serde(default)]
+    containers: Vec<String>,
+    /// Only watch containers with these labels, `key` or `key=value`.
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    restart_loop: RestartLoop,
+}
+
+/// Container started `count` times within `minutes` is reported as restart loop.
+#[derive(Deserialize, Debug, Clone)]
+struct RestartLoop {
+    count: usize,
+    minutes: u64,
+}
+
+impl Default for RestartLoop {
+    fn default() -> Self {
+        RestartLoop { count: 3, minutes: 5 }
+    }
+}
+
+impl RestartLoop {
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.minutes.saturating_mul(60))
+    }
+}
+
+/// Start background task streaming container events and pushing notifications to configured chats.
+pub fn start(bot: Bot, docker: Docker, config: EventsConfig) -> Result<(), String> {
+    if config.chats.is_empty() {
+        return Err("docker.events.chats is empty".to_string());
+    }
+    if config.restart_loop.count == 0 {
+        return Err("docker.events.restart_loop.count must be above 0".to_string());
+    }
+    if config.restart_loop.minutes == 0 || config.restart_loop.minutes.checked_mul(60).is_none() {
+        return Err(format!("docker.events.restart_loop.minutes must be between 1 and {}", u64::MAX / 60));
+    }
+    let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+    filters.insert("type".to_string(), vec!["container".to_string()]);
+    filters.insert("event".to_string(), ["die", "oom", "health_status", "start"].iter().map(|event| event.to_string()).collect());
+    if !config.containers.is_empty() {
+        filters.insert("container".to_string(), config.containers.clone());
+    }
+    if !config.labels.is_empty() {
+        filters.insert("label".to_string(), config.labels.clone());
+    }
+
+    tokio::spawn(async move {
+        let mut starts: HashMap<String, VecDeque<Instant>> = HashMap::new();
+        loop {
+            let options = EventsOptions::<String> {
+                filters: filters.clone(),
+                ..Default::default()
+            };
+            let mut events = docker.events(Some(options));
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(event) => {
+                        if let Some(message) = format_event(&event, &mut starts, &config.restart_loop) {
+                            for chat in &config.chats {
+                                if let Err(x) = bot.send_message(ChatId(*chat), message.clone()).await {
+                                    log::warn!("Failed to send docker event to {}: {}", chat, x);
+                                }
+                            }
+                        }
+                    },
+                    Err(x) => {
+                        log::warn!("Docker event stream failed: {}", x);
+                        break;
+                    }
+                }
+            }
+            // Stream ends when docker restarts or connection drops, subscribe again
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    });
+    Ok(())
+}
+
+fn format_event(event: &EventMessage, starts: &mut HashMap<String, VecDeque<Instant>>, restart_loop: &RestartLoop) -> Option<String> {
+    let action = event.action.as_deref()?;
+    let attributes = event.actor.as_ref().and_then(|actor| actor.attributes.clone()).unwrap_or_default();
+    let name = attributes.get("name").cloned()
+        .or(event.actor.as_ref().and_then(|actor| actor.id.clone()))
+        .unwrap_or("N/A".to_string());
+    let image = attributes.get("image").cloned().unwrap_or("N/A".to_string());
+
+    if action == "die" {
+        Some(format!("Container {} ({}) died with exit code {}", name, image, attributes.get("exitCode").map(|code| code.as_str()).unwrap_or("N/A")))
+    } else if action == "oom" {
+        Some(format!("Container {} ({}) ran out of memory", name, image))
+    } else if action.starts_with("health_status: unhealthy") {
+        Some(format!("Container {} ({}) is unhealthy", name, image))
+    } else if action == "start" {
+        let window = restart_loop.window();
+        let history = starts.entry(name.clone()).or_default();
+        history.push_back(Instant::now());
+        while history.front().is_some_and(|start| start.elapsed() > window) {
+            history.pop_front();
+        }
+        if history.len() >= restart_loop.count {
+            history.clear();
+            Some(format!("Container {} ({}) is in restart loop: started {} times in {}", name, image, restart_loop.count, format_duration(window.as_secs())))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::EventActor;
+
+    fn event(action: &str, attributes: &[(&str, &str)]) -> EventMessage {
+        let mut attributes: HashMap<String, String> = attributes.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        attributes.insert("name".to_string(), "web".to_string());
+        attributes.insert("image".to_string(), "nginx".to_string());
+        EventMessage {
+            action: Some(action.to_string()),
+            actor: Some(EventActor {
+                id: Some("0123456789ab".to_string()),
+                attributes: Some(attributes),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn format(event: &EventMessage) -> Option<String> {
+        format_event(event, &mut HashMap::new(), &RestartLoop::default())
+    }
+
+    fn config(yaml: &str) -> EventsConfig {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn container_failures_are_formatted() {
+        assert_eq!(format(&event("die", &[("exitCode", "137")])).as_deref(), Some("Container web (nginx) died with exit code 137"));
+        assert_eq!(format(&event("oom", &[])).as_deref(), Some("Container web (nginx) ran out of memory"));
+        assert_eq!(format(&event("health_status: unhealthy", &[])).as_deref(), Some("Container web (nginx) is unhealthy"));
+        assert_eq!(format(&event("health_status: healthy", &[])), None);
+        assert_eq!(format(&event("start", &[])), None);
+    }
+
+    #[test]
+    fn restart_loop_is_reported_within_window() {
+        let restart_loop = RestartLoop { count: 3, minutes: 1 };
+        let mut starts = HashMap::new();
+        let start = event("start", &[]);
+        assert_eq!(format_event(&start, &mut starts, &restart_loop), None);
+        assert_eq!(format_event(&start, &mut starts, &restart_loop), None);
+        assert_eq!(format_event(&start, &mut starts, &restart_loop).as_deref(),
+            Some("Container web (nginx) is in restart loop: started 3 times in 1m 0s"));
+        // History is cleared after the report
+        assert_eq!(format_event(&start, &mut starts, &restart_loop), None);
+    }
+
+    #[test]
+    fn starts_outside_of_window_are_forgotten() {
+        let restart_loop = RestartLoop { count: 3, minutes: 1 };
+        let old = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+        let mut starts = HashMap::from([("web".to_string(), VecDeque::from([old, old]))]);
+        assert_eq!(format_event(&event("start", &[]), &mut starts, &restart_loop), None);
+        assert_eq!(starts["web"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_restart_loop_is_rejected() {
+        let docker = || Docker::connect_with_local_defaults().unwrap();
+        let result = start(Bot::new("token"), docker(), config("{chats: [1], restart_loop: {count: 0, minutes: 5}}"));
+        assert_eq!(result.unwrap_err(), "docker.events.restart_loop.count must be above 0");
+        let result = start(Bot::new("token"), docker(), config("{chats: [1], restart_loop: {count: 3, minutes: 0}}"));
+        assert!(result.unwrap_err().starts_with("docker.events.restart_loop.minutes must be between 1 and "));
+        let result = start(Bot::new("token"), docker(), config("{chats: [1], restart_loop: {count: 3, minutes: 18446744073709551615}}"));
+        assert!(result.unwrap_err().starts_with("docker.events.restart_loop.minutes must be between 1 and "));
+    }
+}
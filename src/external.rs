@@ -0,0 +1,424 @@
+use teloxide::{
+    prelude::*,
+    types::{BotCommand, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Me, UpdateKind},
+    utils::command::parse_command,
+};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::audit;
+use crate::plugin::{command_permission, Plugin, UpdateHandler};
+use crate::utils::CALLBACK_DATA_LIMIT;
+
+/// Description of an external plugin in the `plugins` config list.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExternalConfig {
+    name: String,
+    exec: String,
+    #[serde(default)]
+    args: Vec<String>,
+    commands: Vec<String>,
+    help: Option<String>,
+    /// Seconds to wait for the response line, the process is killed when it doesn't answer in time.
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// Request written to the plugin stdin as a single JSON line.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Request {
+    Command {
+        command: String,
+        args: String,
+        chat_id: i64,
+        user_id: Option<u64>,
+        username: Option<String>,
+    },
+    Callback {
+        data: String,
+        chat_id: i64,
+        user_id: u64,
+        username: Option<String>,
+    },
+}
+
+/// Response read from the plugin stdout as a single JSON line.
+#[derive(Deserialize, Debug)]
+struct Response {
+    #[serde(default)]
+    replies: Vec<Reply>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    Text {
+        text: String,
+    },
+    Document {
+        path: Option<String>,
+        name: Option<String>,
+        content: Option<String>,
+        caption: Option<String>,
+    },
+    Keyboard {
+        text: String,
+        buttons: Vec<Vec<Button>>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct Button {
+    text: String,
+    data: String,
+}
+
+struct Process {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+/// Running plugin executable. Requests are sent one at a time, the process is restarted when it exits.
+struct ExternalProcess {
+    config: ExternalConfig,
+    process: Mutex<Option<Process>>,
+}
+
+impl ExternalProcess {
+    fn spawn(config: &ExternalConfig) -> Result<Process, String> {
+        let mut child = Command::new(&config.exec)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|x| format!("Failed to start plugin {}: {}", config.name, x))?;
+        let stdin = child.stdin.take().ok_or("Plugin stdin is not available")?;
+        let stdout = child.stdout.take().ok_or("Plugin stdout is not available")?;
+        Ok(Process {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    async fn request(&self, request: &Request) -> Result<Response, String> {
+        let mut line = serde_json::to_string(request).map_err(|x| x.to_string())?;
+        line.push('\n');
+
+        let mut process = self.process.lock().await;
+        if process.is_none() {
+            *process = Some(Self::spawn(&self.config)?);
+        }
+        let timeout = Duration::from_secs(self.config.timeout);
+        let result = match tokio::time::timeout(timeout, Self::exchange(process.as_mut().unwrap(), &line)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Plugin didn't answer in {}s", self.config.timeout)),
+        };
+        if result.is_err() {
+            // Drop the broken or hung process (it is killed on drop), it will be restarted with the next request
+            *process = None;
+        }
+        result
+    }
+
+    async fn exchange(process: &mut Process, line: &str) -> Result<Response, String> {
+        process.stdin.write_all(line.as_bytes()).await.map_err(|x| x.to_string())?;
+        process.stdin.flush().await.map_err(|x| x.to_string())?;
+
+        let mut response = String::new();
+        match process.stdout.read_line(&mut response).await {
+            Ok(0) => Err("Plugin process exited".to_string()),
+            Ok(_) => serde_json::from_str(&response).map_err(|x| format!("Invalid plugin response: {}", x)),
+            Err(x) => Err(x.to_string()),
+        }
+    }
+}
+
+pub struct ExternalPlugin {
+    config: ExternalConfig,
+}
+
+impl ExternalPlugin {
+    pub fn new(config: ExternalConfig) -> Self {
+        ExternalPlugin { config }
+    }
+}
+
+impl Plugin for ExternalPlugin {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn short_help(&self) -> String {
+        let commands: Vec<String> = self.config.commands.iter().map(|command| format!("/{}", command)).collect();
+        format!("{} plugin (external). Commands: {}", self.config.name, commands.join(", "))
+    }
+
+    fn help(&self) -> String {
+        self.config.help.clone().unwrap_or_else(|| self.short_help())
+    }
+
+    fn commands(&self) -> Vec<BotCommand> {
+        let description = format!("{} plugin", self.config.name);
+        self.config.commands.iter().map(|command| BotCommand::new(command, description.clone())).collect()
+    }
+
+    fn permission(&self, upd: &Update, me: &Me) -> Option<String> {
+        match &upd.kind {
+            UpdateKind::CallbackQuery(query) => query.data.as_ref()
+                .filter(|data| data.starts_with(&format!("ext:{}:", self.config.name)))
+                .map(|_| format!("{}.callback", self.config.name)),
+            _ => command_permission(self, upd, me),
+        }
+    }
+
+    fn update_handler(&self, _config: Option<&serde_yml::Value>) -> Result<UpdateHandler, String> {
+        let process = Arc::new(ExternalProcess {
+            config: self.config.clone(),
+            process: Mutex::new(Some(ExternalProcess::spawn(&self.config)?)),
+        });
+        let commands = self.config.commands.clone();
+        let prefix = format!("ext:{}:", self.config.name);
+        if prefix.len() >= CALLBACK_DATA_LIMIT {
+            return Err(format!("plugin name {} is too long for keyboard buttons", self.config.name));
+        }
+        let callback_prefix = prefix.clone();
+
+        let command_process = process.clone();
+        let command_prefix = prefix.clone();
+        let command_closure = move |bot, msg, request| {
+            answer(bot, msg, request, command_process.clone(), command_prefix.clone())
+        };
+        let callback_closure = move |bot, query| {
+            answer_callback(bot, query, process.clone(), prefix.clone())
+        };
+
+        Ok(dptree::entry()
+            .branch(
+                Update::filter_message()
+                    .filter_map(move |msg: Message, me: Me| to_request(&msg, &me, &commands))
+                    .endpoint(command_closure),
+            )
+            .branch(
+                Update::filter_callback_query()
+                    .filter(move |query: CallbackQuery| {
+                        query.data.as_ref().is_some_and(|data| data.starts_with(&callback_prefix))
+                    })
+                    .endpoint(callback_closure),
+            ))
+    }
+}
+
+fn to_request(msg: &Message, me: &Me, commands: &[String]) -> Option<Arc<Request>> {
+    let (command, args) = parse_command(msg.text()?, me.username())?;
+    if !commands.iter().any(|known| known == command) {
+        return None;
+    }
+    Some(Arc::new(Request::Command {
+        command: command.to_string(),
+        args: args.join(" "),
+        chat_id: msg.chat.id.0,
+        user_id: msg.from().map(|user| user.id.0),
+        username: msg.from().and_then(|user| user.username.clone()),
+    }))
+}
+
+async fn answer(
+    bot: Bot,
+    msg: Message,
+    request: Arc<Request>,
+    process: Arc<ExternalProcess>,
+    prefix: String) -> ResponseResult<()> {
+    send_replies(&bot, msg.chat.id, process.request(&request).await, &prefix).await
+}
+
+async fn answer_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    process: Arc<ExternalProcess>,
+    prefix: String) -> ResponseResult<()> {
+    bot.answer_callback_query(query.id.clone()).await?;
+    if let (Some(data), Some(msg)) = (&query.data, &query.message) {
+        let request = Request::Callback {
+            data: data[prefix.len()..].to_string(),
+            chat_id: msg.chat.id.0,
+            user_id: query.from.id.0,
+            username: query.from.username.clone(),
+        };
+        send_replies(&bot, msg.chat.id, process.request(&request).await, &prefix).await?;
+    }
+    Ok(())
+}
+
+async fn send_replies(bot: &Bot, chat_id: ChatId, response: Result<Response, String>, prefix: &str) -> ResponseResult<()> {
+    let response = match response {
+        Ok(response) => response,
+        Err(x) => {
+            bot.send_message(chat_id, audit::failure(format!("Plugin failed with err: {}", x))).await?;
+            return Ok(());
+        }
+    };
+
+    for reply in response.replies {
+        match reply {
+            Reply::Text { text } => {
+                bot.send_message(chat_id, text).await?;
+            },
+            Reply::Document { path, name, content, caption } => {
+                let file = match (path, content) {
+                    (Some(path), _) => InputFile::file(path),
+                    (None, Some(content)) => InputFile::memory(content).file_name("reply.txt"),
+                    (None, None) => {
+                        bot.send_message(chat_id, "Plugin sent document without path or content").await?;
+                        continue;
+                    }
+                };
+                let file = match name {
+                    Some(name) => file.file_name(name),
+                    None => file,
+                };
+                let mut request = bot.send_document(chat_id, file);
+                if let Some(caption) = caption {
+                    request = request.caption(caption);
+                }
+                request.await?;
+            },
+            Reply::Keyboard { text, buttons } => match keyboard(buttons, prefix) {
+                Ok(keyboard) => {
+                    bot.send_message(chat_id, text).reply_markup(keyboard).await?;
+                },
+                Err(x) => {
+                    bot.send_message(chat_id, audit::failure(format!("Plugin sent invalid keyboard: {}", x))).await?;
+                },
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Keyboard of plugin buttons, their data is prefixed with `ext:<plugin name>:` to route callbacks back to the plugin.
+fn keyboard(buttons: Vec<Vec<Button>>, prefix: &str) -> Result<InlineKeyboardMarkup, String> {
+    let mut rows = Vec::new();
+    for row in buttons {
+        let mut keys = Vec::new();
+        for button in row {
+            let data = format!("{}{}", prefix, button.data);
+            if data.len() > CALLBACK_DATA_LIMIT {
+                return Err(format!("data of button {} is longer than {} bytes", button.text, CALLBACK_DATA_LIMIT - prefix.len()));
+            }
+            keys.push(InlineKeyboardButton::callback(button.text, data));
+        }
+        rows.push(keys);
+    }
+    Ok(InlineKeyboardMarkup::new(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(exec: &str, args: &[&str], timeout: u64) -> ExternalProcess {
+        let config = ExternalConfig {
+            name: "echo".to_string(),
+            exec: exec.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            commands: vec!["echo".to_string()],
+            help: None,
+            timeout,
+        };
+        ExternalProcess {
+            config,
+            process: Mutex::new(None),
+        }
+    }
+
+    fn echo_plugin() -> ExternalProcess {
+        process("python3", &[concat!(env!("CARGO_MANIFEST_DIR"), "/contrib/echo-plugin.py")], 10)
+    }
+
+    fn command(args: &str) -> Request {
+        Request::Command {
+            command: "echo".to_string(),
+            args: args.to_string(),
+            chat_id: 1,
+            user_id: Some(2),
+            username: Some("user".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_plugin_answers_commands_and_callbacks() {
+        let plugin = echo_plugin();
+
+        let response = plugin.request(&command("hello")).await.unwrap();
+        assert!(matches!(response.replies.as_slice(), [Reply::Text { text }] if text == "hello"));
+
+        let response = plugin.request(&command("keyboard")).await.unwrap();
+        assert!(matches!(response.replies.as_slice(), [Reply::Keyboard { buttons, .. }] if buttons[0].len() == 2 && buttons[0][1].data == "b"));
+
+        let response = plugin.request(&command("file")).await.unwrap();
+        assert!(matches!(response.replies.as_slice(), [Reply::Document { name: Some(name), content: Some(_), .. }] if name == "echo.txt"));
+
+        let callback = Request::Callback {
+            data: "a".to_string(),
+            chat_id: 1,
+            user_id: 2,
+            username: None,
+        };
+        let response = plugin.request(&callback).await.unwrap();
+        assert!(matches!(response.replies.as_slice(), [Reply::Text { text }] if text == "You picked a"));
+    }
+
+    #[tokio::test]
+    async fn hung_plugin_times_out_and_is_dropped() {
+        let plugin = process("sh", &["-c", "cat > /dev/null"], 1);
+        let result = plugin.request(&command("hello")).await;
+        assert_eq!(result.unwrap_err(), "Plugin didn't answer in 1s");
+        assert!(plugin.process.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn exited_plugin_is_restarted() {
+        let plugin = process("sh", &["-c", "read line; echo '{\"replies\": []}'"], 10);
+        assert!(plugin.request(&command("hello")).await.unwrap().replies.is_empty());
+        assert!(plugin.request(&command("hello")).await.is_err());
+        assert!(plugin.request(&command("hello")).await.unwrap().replies.is_empty());
+    }
+
+    fn buttons(data: &[&str]) -> Vec<Vec<Button>> {
+        vec![data.iter().map(|data| Button { text: data.to_uppercase(), data: data.to_string() }).collect()]
+    }
+
+    #[test]
+    fn keyboard_data_is_prefixed() {
+        let keyboard = keyboard(buttons(&["a", "b"]), "ext:echo:").unwrap();
+        let data: Vec<String> = keyboard.inline_keyboard[0].iter()
+            .map(|button| match &button.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => data.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(data, ["ext:echo:a", "ext:echo:b"]);
+    }
+
+    #[test]
+    fn too_long_keyboard_data_is_rejected() {
+        let longest = "x".repeat(CALLBACK_DATA_LIMIT - "ext:echo:".len());
+        assert!(keyboard(buttons(&[&longest]), "ext:echo:").is_ok());
+        let too_long = format!("{}x", longest);
+        assert_eq!(keyboard(buttons(&["a", &too_long]), "ext:echo:").unwrap_err(),
+            format!("data of button {} is longer than 55 bytes", too_long.to_uppercase()));
+    }
+}
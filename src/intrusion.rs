@@ -0,0 +1,243 @@
+use teloxide::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::access::AccessControl;
+use crate::audit;
+
+/// `intrusion` config section.
+#[derive(Deserialize, Debug)]
+pub struct IntrusionConfig {
+    #[serde(default)]
+    mode: AlertMode,
+    /// Chats receiving alerts. Users allowed `intrusion` permission are used when empty.
+    #[serde(default)]
+    chats: Vec<i64>,
+    /// Minimal number of seconds between alerts about the same user.
+    #[serde(default = "default_throttle")]
+    throttle: u64,
+    /// Ban user without any role after this number of denied attempts.
+    ban_after: Option<u32>,
+    /// File where banned user ids are stored.
+    ban_file: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMode {
+    Off,
+    /// Alert only about users without any role.
+    #[default]
+    Unknown,
+    /// Alert about every denied update.
+    All,
+}
+
+fn default_throttle() -> u64 {
+    600
+}
+
+#[derive(Default)]
+struct Offender {
+    attempts: u32,
+    suppressed: u32,
+    last_alert: Option<Instant>,
+}
+
+/// Alert about denied attempts of one user.
+struct Alert {
+    attempts: u32,
+    /// Attempts not alerted about since the previous alert.
+    suppressed: u32,
+    /// User was banned by this attempt.
+    banned: bool,
+}
+
+/// Alerts about denied updates and bans users that keep trying.
+pub struct Intrusion {
+    config: IntrusionConfig,
+    recipients: Vec<ChatId>,
+    offenders: Mutex<HashMap<UserId, Offender>>,
+    banned: Mutex<HashSet<u64>>,
+}
+
+impl Intrusion {
+    pub fn new(config: IntrusionConfig, access_control: &AccessControl) -> Result<Intrusion, String> {
+        let banned = match &config.ban_file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(data) => serde_json::from_str(&data).map_err(|x| format!("couldn't parse {}: {}", path, x))?,
+                Err(x) if x.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+                Err(x) => return Err(format!("couldn't read {}: {}", path, x)),
+            },
+            None => HashSet::new(),
+        };
+        let recipients = if config.chats.is_empty() {
+            access_control.users_allowed("intrusion").into_iter().map(|user| ChatId(user as i64)).collect()
+        } else {
+            config.chats.iter().map(|chat| ChatId(*chat)).collect()
+        };
+        Ok(Intrusion {
+            config,
+            recipients,
+            offenders: Mutex::new(HashMap::new()),
+            banned: Mutex::new(banned),
+        })
+    }
+
+    pub fn is_banned(&self, upd: &Update) -> bool {
+        upd.user().is_some_and(|user| self.banned.lock().unwrap().contains(&user.id.0))
+    }
+
+    /// Register denied update. `unknown` is true when the sender has no role at all.
+    pub async fn report(&self, bot: &Bot, upd: &Update, unknown: bool) {
+        let Some(user) = upd.user() else {
+            return;
+        };
+        let Some(alert) = self.register(user.id, unknown) else {
+            return;
+        };
+
+        let mut message = format!("Unauthorized access attempt\nUser: {} ({})\nChat: {}\nText: {}\nAttempts: {}",
+            user.id,
+            user.username.as_deref().map(|username| format!("@{}", username)).unwrap_or("N/A".to_string()),
+            upd.chat().map(|chat| chat.id.to_string()).unwrap_or("N/A".to_string()),
+            audit::update_text(upd),
+            alert.attempts
+        );
+        if alert.suppressed > 0 {
+            message += format!("\nSuppressed alerts: {}", alert.suppressed).as_str();
+        }
+        if alert.banned {
+            message += "\nUser is banned";
+        }
+        for chat in &self.recipients {
+            if let Err(x) = bot.send_message(*chat, message.clone()).await {
+                log::warn!("Failed to send intrusion alert to {}: {}", chat, x);
+            }
+        }
+    }
+
+    /// Count denied attempt of the user. Returns `None` when no alert should be sent, because
+    /// alerts are off for the user or were already sent within `throttle` seconds.
+    /// Members of roles are never banned, they are only alerted about in `all` mode.
+    fn register(&self, user: UserId, unknown: bool) -> Option<Alert> {
+        if self.config.mode == AlertMode::Off || (self.config.mode == AlertMode::Unknown && !unknown) {
+            return None;
+        }
+
+        let mut offenders = self.offenders.lock().unwrap();
+        let offender = offenders.entry(user).or_default();
+        offender.attempts += 1;
+        let throttled = offender.last_alert.is_some_and(|last| last.elapsed() < Duration::from_secs(self.config.throttle));
+        let banned = unknown && self.config.ban_after.is_some_and(|ban_after| offender.attempts >= ban_after) && self.ban(user);
+        if throttled && !banned {
+            offender.suppressed += 1;
+            return None;
+        }
+        // Every sent alert, including the ban alert, starts a new throttle window
+        offender.last_alert = Some(Instant::now());
+        Some(Alert {
+            attempts: offender.attempts,
+            suppressed: std::mem::take(&mut offender.suppressed),
+            banned,
+        })
+    }
+
+    /// Add user to the ban list. Returns false when the user was already banned.
+    fn ban(&self, user: UserId) -> bool {
+        let mut banned = self.banned.lock().unwrap();
+        if !banned.insert(user.0) {
+            return false;
+        }
+        if let Some(path) = &self.config.ban_file {
+            let result = serde_json::to_string(&*banned)
+                .map_err(|x| x.to_string())
+                .and_then(|data| std::fs::write(path, data).map_err(|x| x.to_string()));
+            if let Err(x) = result {
+                log::error!("Failed to save ban list {}: {}", path, x);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intrusion(yaml: &str) -> Intrusion {
+        let access_control = AccessControl::new(Some(vec![1]), None).unwrap();
+        Intrusion::new(serde_yml::from_str(yaml).unwrap(), &access_control).unwrap()
+    }
+
+    fn ban_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("tg-captain-bans-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    fn attempts(alert: Option<Alert>) -> Option<(u32, u32, bool)> {
+        alert.map(|alert| (alert.attempts, alert.suppressed, alert.banned))
+    }
+
+    #[test]
+    fn alerts_are_throttled_per_user() {
+        let intrusion = intrusion("throttle: 600");
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), Some((1, 0, false)));
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), None);
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), None);
+        assert_eq!(attempts(intrusion.register(UserId(8), true)), Some((1, 0, false)));
+
+        // Alert after the throttle window summarizes suppressed attempts
+        let window = Duration::from_secs(601);
+        intrusion.offenders.lock().unwrap().get_mut(&UserId(7)).unwrap().last_alert = Instant::now().checked_sub(window);
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), Some((4, 2, false)));
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), None);
+    }
+
+    #[test]
+    fn alert_mode_selects_reported_users() {
+        assert_eq!(attempts(intrusion("mode: off").register(UserId(7), true)), None);
+        assert_eq!(attempts(intrusion("mode: unknown").register(UserId(7), false)), None);
+        assert_eq!(attempts(intrusion("mode: all").register(UserId(7), false)), Some((1, 0, false)));
+    }
+
+    #[test]
+    fn user_is_banned_after_attempts() {
+        let intrusion = intrusion("{throttle: 600, ban_after: 3}");
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), Some((1, 0, false)));
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), None);
+        // Ban is reported even within the throttle window, but only once
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), Some((3, 1, true)));
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), None);
+        assert!(intrusion.banned.lock().unwrap().contains(&7));
+
+        // Attempts reported with the ban aren't counted again by the next alert
+        let window = Duration::from_secs(601);
+        intrusion.offenders.lock().unwrap().get_mut(&UserId(7)).unwrap().last_alert = Instant::now().checked_sub(window);
+        assert_eq!(attempts(intrusion.register(UserId(7), true)), Some((5, 1, false)));
+    }
+
+    #[test]
+    fn role_members_are_not_banned() {
+        let intrusion = intrusion("{mode: all, throttle: 0, ban_after: 1}");
+        assert_eq!(attempts(intrusion.register(UserId(7), false)), Some((1, 0, false)));
+        assert_eq!(attempts(intrusion.register(UserId(7), false)), Some((2, 0, false)));
+        assert!(intrusion.banned.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn bans_are_reloaded_from_file() {
+        let path = ban_file("reload");
+        let config = format!("{{ban_after: 1, ban_file: {}}}", path);
+        assert!(intrusion(&config).register(UserId(7), true).unwrap().banned);
+
+        let reloaded = intrusion(&config);
+        assert!(reloaded.banned.lock().unwrap().contains(&7));
+        // Already banned user isn't reported as banned again
+        assert!(!reloaded.register(UserId(7), true).unwrap().banned);
+        let _ = std::fs::remove_file(&path);
+    }
+}
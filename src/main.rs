@@ -1,67 +1,66 @@
 use std::collections::HashMap;
 use teloxide::prelude::*;
+use teloxide::types::{Me, UpdateKind};
 use serde::Deserialize;
 use teloxide::utils::command::BotCommands;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::env;
+use std::sync::Arc;
 
+mod access;
+mod audit;
+mod confirm;
+mod intrusion;
+mod monitor;
+mod plugin;
+mod qbittorrent;
+mod external;
 mod system;
+mod torrent_client;
 mod transmission;
 mod docker;
+mod docker_events;
+mod uptime;
+mod utils;
+#[cfg(test)]
+mod test_utils;
 
 #[derive(Deserialize, Debug)]
 struct Config {
     token: String,
     security: bool,
     admins: Option<Vec<u64>>,
-    plugins: Vec<String>,
-    sys: Option<Sys>,
-    docker: Option<Docker>,
-    transmission: Option<Transmission>,
-}
-
-#[derive(Deserialize, Debug)]
-struct Docker {
-    mode: String,
-    path: Option<String>,
-}
-
-#[derive(Deserialize, Debug)]
-struct Transmission {
-    rpc: String,
-}
-
-#[derive(Deserialize, Debug)]
-struct Sys {
-    ping: HashMap<String, String>,
-}
-
-#[derive(Clone)]
-struct SecurityParameters {
-    admins: Vec<UserId>,
+    roles: Option<HashMap<String, access::Role>>,
+    confirmation: Option<confirm::ConfirmationConfig>,
+    audit: Option<audit::AuditConfig>,
+    intrusion: Option<intrusion::IntrusionConfig>,
+    plugins: Vec<plugin::PluginEntry>,
+    #[serde(flatten)]
+    sections: HashMap<String, serde_yml::Value>,
 }
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command{
-    Help
+    #[command(description = "show help. Use /help [plugin] for plugin details")]
+    Help(String),
+    #[command(description = "show recent audit entries. Usage /audit [n] [user]")]
+    Audit(String),
 }
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
     let args: Vec<String> = env::args().collect();
-    let path: &Path;
-    if args.len() >= 2{
-        path = Path::new(&args[1]);
+    let path: &Path = if args.len() >= 2{
+        Path::new(&args[1])
     } else {
         panic!("Please provide config file path");
-    }
-    
+    };
 
-    let mut file = match File::open(&path) {
+    let mut file = match File::open(path) {
         Err(why) => panic!("couldn't open {}: {}", path.display(), why),
         Ok(file) => file,
     };
@@ -78,75 +77,93 @@ async fn main() {
 
     let bot = Bot::new(config.token);
     
-    let mut plugin_handler = Update::filter_message();
+    let mut plugin_handler = dptree::entry();
 
-    let mut enabled_plugin: Vec<String> = Vec::new();
+    let mut enabled_plugins: Vec<Box<dyn plugin::Plugin>> = Vec::new();
     let mut help_text = "TG-CAPTAIN help\n\n".to_string();
+    let mut detailed_help: HashMap<String, String> = HashMap::new();
+    let mut commands = Command::bot_commands();
 
-    for plugin in config.plugins{
-        if enabled_plugin.contains(&plugin){
-            println!("Plugin {} is alredy enabled", plugin);
-        } else {
-            if plugin == "sys"{
-                if let Some(ref sys_config) = config.sys {
-                    plugin_handler = plugin_handler.branch(system::get_update_handler(sys_config.ping.clone()));
-                    help_text += system::get_short_help().as_str();
-                    help_text += "\n";
-                } else {
-                    panic!("Sys Config is not present");
-                }
-            } else if plugin == "transmission"{
-                if let Some(ref transmission_config) = config.transmission {
-                    plugin_handler = plugin_handler.branch(transmission::get_update_handler(&transmission_config.rpc));
-                    help_text += transmission::get_short_help().as_str();
-                    help_text += "\n";
-                } else {
-                    panic!("Transmission Config is not present");
-                }
-            } else if plugin == "docker"{
-                if let Some(ref docker_config) = config.docker {
-                    plugin_handler = plugin_handler.branch(docker::get_update_handler(&docker_config.mode, &docker_config.path.clone().unwrap_or_default()));
-                    help_text += docker::get_short_help().as_str();
-                    help_text += "\n";
-                } else {
-                    panic!("Transmission Config is not present");
-                }
-            } else {
-                println!("Plugin {} not found", plugin);
+    for entry in config.plugins{
+        let plugin = match entry.resolve() {
+            Ok(plugin) => plugin,
+            Err(x) => {
+                println!("{}", x);
+                continue;
             }
+        };
+        let plugin_name = plugin.name().to_string();
+        if enabled_plugins.iter().any(|enabled| enabled.name() == plugin_name){
+            println!("Plugin {} is alredy enabled", plugin_name);
+            continue;
         }
-        enabled_plugin.push(plugin);
+        match plugin.update_handler(config.sections.get(&plugin_name)) {
+            Ok(update_handler) => plugin_handler = plugin_handler.branch(update_handler),
+            Err(x) => panic!("{}", x)
+        }
+        if let Err(x) = plugin.start(bot.clone(), config.sections.get(&plugin_name)) {
+            panic!("{}", x)
+        }
+        help_text += plugin.short_help().as_str();
+        help_text += "\n";
+        detailed_help.insert(plugin_name.clone(), plugin.help());
+        commands.extend(plugin.commands());
+        enabled_plugins.push(plugin);
     }
+    let plugins: plugin::Plugins = Arc::new(enabled_plugins);
 
-    let help_closure = move |bot: Bot, msg: Message|{
-        show_help(bot, msg, help_text.clone())
-    };
-    plugin_handler = plugin_handler.branch(dptree::entry().filter_command::<Command>().endpoint(help_closure));
+    if let Err(x) = bot.set_my_commands(commands).await {
+        log::warn!("Failed to register bot commands: {}", x);
+    }
 
-    let mut handler = Update::filter_message();
+    let audit = config.audit.map(|audit_config| match audit::Audit::open(audit_config) {
+        Ok(audit) => Arc::new(audit),
+        Err(x) => panic!("{}", x)
+    });
 
-    let mut admins_data: Vec<UserId> = Vec::new();
+    let command_audit = audit.clone();
+    let command_closure = move |bot: Bot, msg: Message, cmd: Command|{
+        command_handler(bot, msg, cmd, help_text.clone(), detailed_help.clone(), command_audit.clone())
+    };
+    plugin_handler = plugin_handler.branch(Update::filter_message().filter_command::<Command>().endpoint(command_closure));
+    if let Some(audit) = &audit {
+        plugin_handler = audit::wrap(audit.clone(), plugin_handler);
+    }
+    let confirmation = confirm::Confirmation::new(config.confirmation.unwrap_or_default(), audit.clone(), plugins.clone());
+    let plugin_handler = confirm::guard(confirmation.clone(), plugin_handler);
+
+    let mut handler = dptree::entry();
 
     if config.security{
-        match config.admins{
-            Some(data) => admins_data = data.iter().map(|id| UserId(id.to_owned())).collect(),
-            None => panic!("Security activated but no admins provided!")
-        }
-        handler = handler.branch(dptree::filter(|cfg: SecurityParameters, msg: Message| {
-            if let Some(user) = msg.from() {
-                cfg.admins.contains(&user.id)
-            } else {
-                false
-            }
+        let access_control = match access::AccessControl::new(config.admins, config.roles){
+            Ok(access_control) => Arc::new(access_control),
+            Err(x) => panic!("{}", x)
+        };
+        let intrusion = config.intrusion.map(|intrusion_config| match intrusion::Intrusion::new(intrusion_config, &access_control) {
+            Ok(intrusion) => Arc::new(intrusion),
+            Err(x) => panic!("{}", x)
+        });
+        let denied_access_control = access_control.clone();
+        let denied_intrusion = intrusion.clone();
+        let denied_closure = move |bot: Bot, upd: Update, plugins: plugin::Plugins, me: Me| {
+            denied(bot, upd, plugins, me, audit.clone(), denied_access_control.clone(), denied_intrusion.clone())
+        };
+        handler = handler.branch(dptree::filter(move |resolved: plugin::Resolved, upd: Update| {
+            let banned = intrusion.as_ref().is_some_and(|intrusion| intrusion.is_banned(&upd));
+            !banned && access_control.allows_all(&upd, resolved.0.as_deref())
         }).branch(plugin_handler))
+        .branch(dptree::endpoint(denied_closure))
+    } else {
+        handler = handler.branch(plugin_handler);
     }
 
-    let security_parameters = SecurityParameters{
-        admins: admins_data
-    };
+    // Targets are resolved once, so they can't change between the checks and the handler
+    let handler = dptree::filter_map_async(|plugins: plugin::Plugins, upd: Update, me: Me| async move {
+        Some(plugin::Resolved(permissions(&plugins, &upd, &me).await))
+    }).chain(handler);
 
     Dispatcher::builder(bot, handler)
-    .dependencies(dptree::deps![security_parameters])
+    .dependencies(dptree::deps![plugins, confirmation])
     .default_handler(|upd| async move {
         log::warn!("Unhandled update: {:?}", upd);
     })
@@ -159,7 +176,107 @@ async fn main() {
     .await;
 }
 
-async fn show_help(bot: Bot, msg: Message, help_text: String )-> ResponseResult<()>{
-    bot.send_message(msg.chat.id, help_text).await?;
+/// Permission of the update: `audit` for the audit command, otherwise permission of the plugin handling it.
+fn permission(plugins: &plugin::Plugins, upd: &Update, me: &Me) -> Option<String> {
+    if let UpdateKind::Message(msg) = &upd.kind {
+        if let Some(Ok(Command::Audit(_))) = msg.text().map(|text| Command::parse(text, me.username())) {
+            return Some("audit".to_string());
+        }
+    }
+    plugin::permission(plugins, upd, me)
+}
+
+/// Update that would be handled by the bot: command, button or dropped torrent of one of the plugins, `/help` or `/audit`.
+fn addressed_to_bot(plugins: &plugin::Plugins, upd: &Update, me: &Me) -> bool {
+    if permission(plugins, upd, me).is_some() {
+        return true;
+    }
+    match &upd.kind {
+        UpdateKind::Message(msg) => msg.text().is_some_and(|text| Command::parse(text, me.username()).is_ok()),
+        _ => false,
+    }
+}
+
+/// Permissions checked by access control, see `plugin::permissions`.
+async fn permissions(plugins: &plugin::Plugins, upd: &Update, me: &Me) -> Option<Vec<String>> {
+    match permission(plugins, upd, me) {
+        Some(permission) if permission == "audit" => Some(vec![permission]),
+        _ => plugin::permissions(plugins, upd, me).await,
+    }
+}
+
+async fn command_handler(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    help_text: String,
+    detailed_help: HashMap<String, String>,
+    audit: Option<Arc<audit::Audit>>) -> ResponseResult<()>{
+    match cmd {
+        Command::Help(plugin_name) => {
+            let text = detailed_help.get(plugin_name.trim()).unwrap_or(&help_text);
+            bot.send_message(msg.chat.id, text).await?;
+        },
+        Command::Audit(args) => {
+            let args: Vec<&str> = args.split_whitespace().collect();
+            let (count, user) = match args.first().and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) => (count.min(50), args.get(1).copied()),
+                None => (10, args.first().copied())
+            };
+            let text = match audit {
+                Some(audit) => audit.recent(count, user),
+                None => "Audit is disabled".to_string()
+            };
+            for part in utils::split_message(&text) {
+                bot.send_message(msg.chat.id, part).await?;
+            }
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn denied(
+    bot: Bot,
+    upd: Update,
+    plugins: plugin::Plugins,
+    me: Me,
+    audit: Option<Arc<audit::Audit>>,
+    access_control: Arc<access::AccessControl>,
+    intrusion: Option<Arc<intrusion::Intrusion>>) -> ResponseResult<()>{
+    if intrusion.as_ref().is_some_and(|intrusion| intrusion.is_banned(&upd)) {
+        return Ok(());
+    }
+    if !addressed_to_bot(&plugins, &upd, &me) {
+        // Ordinary messages in groups with the bot are not attempts to use it
+        log::debug!("Ignored update: {:?}", upd);
+        return Ok(());
+    }
+    log::warn!("Access denied: {:?}", upd);
+    if let Some(audit) = audit {
+        audit.record(&upd, &plugins, &me, audit::Outcome::Denied);
+    }
+    if let Some(intrusion) = intrusion {
+        intrusion.report(&bot, &upd, !access_control.allows(&upd, None)).await;
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::me;
+
+    fn message(text: &str) -> Update {
+        crate::test_utils::message(7, -1, text)
+    }
+
+    #[test]
+    fn only_updates_for_bot_are_reported() {
+        let plugins: plugin::Plugins = Arc::new(plugin::registry());
+        assert!(addressed_to_bot(&plugins, &message("/docker container list"), &me()));
+        assert!(addressed_to_bot(&plugins, &message("/help"), &me()));
+        assert!(addressed_to_bot(&plugins, &message("/audit 5"), &me()));
+        assert!(addressed_to_bot(&plugins, &message("magnet:?xt=urn:btih:abc"), &me()));
+        assert!(!addressed_to_bot(&plugins, &message("hello everyone"), &me()));
+        assert!(!addressed_to_bot(&plugins, &message("/docker@other_bot"), &me()));
+    }
+}
@@ -0,0 +1,203 @@
+use teloxide::prelude::*;
+use sysinfo::{Components, Disks, System};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// `sys.monitor` config section.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MonitorConfig {
+    /// Seconds between samples.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    chats: Vec<i64>,
+    /// Value must drop this much below threshold before recovery is reported.
+    #[serde(default = "default_hysteresis")]
+    hysteresis: f32,
+    cpu: Option<Threshold>,
+    ram: Option<Threshold>,
+    /// Used space in percent by mount point, `*` matches every mount.
+    #[serde(default)]
+    disks: HashMap<String, Threshold>,
+    /// Temperature in °C by component label, `*` matches every component.
+    #[serde(default)]
+    temperature: HashMap<String, Threshold>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Threshold {
+    above: f32,
+    /// Minutes the value must stay above threshold before alert is sent.
+    #[serde(default)]
+    minutes: u64,
+}
+
+fn default_interval() -> u64 {
+    30
+}
+
+fn default_hysteresis() -> f32 {
+    5.0
+}
+
+#[derive(Default)]
+struct Alarm {
+    above_since: Option<Instant>,
+    active: bool,
+}
+
+/// Tracks alarms for the checked values and produces alert and recovery messages.
+struct Alarms {
+    hysteresis: f32,
+    alarms: HashMap<String, Alarm>,
+}
+
+impl Alarms {
+    fn check(&mut self, name: &str, value: f32, unit: &str, threshold: &Threshold) -> Option<String> {
+        let alarm = self.alarms.entry(name.to_string()).or_default();
+        if value > threshold.above {
+            let since = *alarm.above_since.get_or_insert_with(Instant::now);
+            if !alarm.active && since.elapsed() >= Duration::from_secs(threshold.minutes.saturating_mul(60)) {
+                alarm.active = true;
+                return Some(format!("ALERT: {} is {:.1}{} (above {:.1}{})", name, value, unit, threshold.above, unit));
+            }
+        } else {
+            alarm.above_since = None;
+            if alarm.active && value < threshold.above - self.hysteresis {
+                alarm.active = false;
+                return Some(format!("RECOVERED: {} is {:.1}{}", name, value, unit));
+            }
+        }
+        None
+    }
+}
+
+fn threshold_for<'a>(thresholds: &'a HashMap<String, Threshold>, name: &str) -> Option<&'a Threshold> {
+    thresholds.get(name).or(thresholds.get("*"))
+}
+
+/// Start background task sampling system state and pushing alerts to configured chats.
+pub fn start(bot: Bot, config: MonitorConfig) -> Result<(), String> {
+    if config.chats.is_empty() {
+        return Err("sys.monitor.chats is empty".to_string());
+    }
+    if config.interval == 0 {
+        return Err("sys.monitor.interval must be above 0".to_string());
+    }
+    tokio::spawn(async move {
+        let mut sys = System::new();
+        let mut alarms = Alarms {
+            hysteresis: config.hysteresis,
+            alarms: HashMap::new(),
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+        loop {
+            interval.tick().await;
+            for message in sample(&mut sys, &mut alarms, &config) {
+                for chat in &config.chats {
+                    if let Err(x) = bot.send_message(ChatId(*chat), message.clone()).await {
+                        log::warn!("Failed to send monitor alert to {}: {}", chat, x);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn sample(sys: &mut System, alarms: &mut Alarms, config: &MonitorConfig) -> Vec<String> {
+    let mut messages = Vec::new();
+
+    if let Some(threshold) = &config.cpu {
+        sys.refresh_cpu_usage();
+        messages.extend(alarms.check("CPU load", sys.global_cpu_info().cpu_usage(), "%", threshold));
+    }
+    if let Some(threshold) = &config.ram {
+        sys.refresh_memory();
+        if sys.total_memory() > 0 {
+            let used = sys.used_memory() as f32 / sys.total_memory() as f32 * 100.0;
+            messages.extend(alarms.check("RAM usage", used, "%", threshold));
+        }
+    }
+    if !config.disks.is_empty() {
+        let disks = Disks::new_with_refreshed_list();
+        for disk in &disks {
+            let mount = disk.mount_point().to_string_lossy().to_string();
+            if let Some(threshold) = threshold_for(&config.disks, &mount) {
+                if disk.total_space() > 0 {
+                    let used = (disk.total_space() - disk.available_space()) as f32 / disk.total_space() as f32 * 100.0;
+                    messages.extend(alarms.check(&format!("Disk {}", mount), used, "%", threshold));
+                }
+            }
+        }
+    }
+    if !config.temperature.is_empty() {
+        let components = Components::new_with_refreshed_list();
+        for component in &components {
+            if let Some(threshold) = threshold_for(&config.temperature, component.label()) {
+                messages.extend(alarms.check(component.label(), component.temperature(), "°C", threshold));
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarms() -> Alarms {
+        Alarms {
+            hysteresis: 5.0,
+            alarms: HashMap::new(),
+        }
+    }
+
+    fn threshold(above: f32, minutes: u64) -> Threshold {
+        Threshold { above, minutes }
+    }
+
+    #[test]
+    fn alert_is_sent_once_and_recovers_below_hysteresis() {
+        let mut alarms = alarms();
+        let threshold = threshold(90.0, 0);
+        assert_eq!(alarms.check("CPU load", 95.0, "%", &threshold).as_deref(), Some("ALERT: CPU load is 95.0% (above 90.0%)"));
+        assert_eq!(alarms.check("CPU load", 97.0, "%", &threshold), None);
+        assert_eq!(alarms.check("CPU load", 88.0, "%", &threshold), None);
+        assert_eq!(alarms.check("CPU load", 80.0, "%", &threshold).as_deref(), Some("RECOVERED: CPU load is 80.0%"));
+        assert_eq!(alarms.check("CPU load", 70.0, "%", &threshold), None);
+    }
+
+    #[test]
+    fn alert_waits_for_minutes_above_threshold() {
+        let mut alarms = alarms();
+        let threshold = threshold(90.0, 5);
+        assert_eq!(alarms.check("RAM usage", 95.0, "%", &threshold), None);
+        assert_eq!(alarms.check("RAM usage", 95.0, "%", &threshold), None);
+        assert!(alarms.alarms["RAM usage"].above_since.is_some());
+        assert_eq!(alarms.check("RAM usage", 50.0, "%", &threshold), None);
+        assert!(alarms.alarms["RAM usage"].above_since.is_none());
+    }
+
+    #[test]
+    fn values_are_tracked_by_name() {
+        let mut alarms = alarms();
+        let threshold = threshold(80.0, 0);
+        assert!(alarms.check("Disk /", 85.0, "%", &threshold).is_some());
+        assert!(alarms.check("Disk /data", 85.0, "%", &threshold).is_some());
+    }
+
+    #[test]
+    fn wildcard_threshold_is_used_for_unknown_names() {
+        let thresholds = HashMap::from([("*".to_string(), threshold(80.0, 0)), ("/".to_string(), threshold(95.0, 0))]);
+        assert_eq!(threshold_for(&thresholds, "/").map(|threshold| threshold.above), Some(95.0));
+        assert_eq!(threshold_for(&thresholds, "/data").map(|threshold| threshold.above), Some(80.0));
+        assert!(threshold_for(&HashMap::new(), "/").is_none());
+    }
+
+    #[tokio::test]
+    async fn zero_interval_is_rejected() {
+        let config: MonitorConfig = serde_yml::from_str("interval: 0\nchats: [1]").unwrap();
+        assert_eq!(start(Bot::new("token"), config).unwrap_err(), "sys.monitor.interval must be above 0");
+    }
+}
@@ -0,0 +1,203 @@
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    prelude::*,
+    types::{BotCommand, Me, UpdateKind},
+    utils::command::parse_command,
+    RequestError,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{access, docker, external, system, transmission};
+
+pub type UpdateHandler = Handler<'static, DependencyMap, Result<(), RequestError>, DpHandlerDescription>;
+
+/// Plugins enabled in config, shared with the dispatcher.
+pub type Plugins = Arc<Vec<Box<dyn Plugin>>>;
+
+/// Common interface of every tg-captain plugin.
+///
+/// A plugin owns a section of the config file named after the plugin and contributes
+/// a branch to the dispatcher built in `main`.
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    /// Name used in the `plugins` list and as the key of the plugin config section.
+    fn name(&self) -> &str;
+
+    /// Example of the plugin config section. Plugins returning `None` need no config.
+    fn config_schema(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn short_help(&self) -> String;
+
+    fn help(&self) -> String;
+
+    /// Commands registered in the telegram command menu.
+    fn commands(&self) -> Vec<BotCommand>;
+
+    fn update_handler(&self, config: Option<&serde_yml::Value>) -> Result<UpdateHandler, String>;
+
+    /// Start background tasks of the plugin, e.g. monitors pushing notifications to chats.
+    fn start(&self, _bot: Bot, _config: Option<&serde_yml::Value>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Permission required to handle the update, e.g. `docker.container.kill.web`.
+    /// `None` means that the update is not handled by the plugin.
+    fn permission(&self, upd: &Update, me: &Me) -> Option<String> {
+        command_permission(self, upd, me)
+    }
+
+    /// Normalize command arguments into permission segments, so aliases and default modes
+    /// are checked the same way as their full form.
+    fn action<'a>(&self, args: Vec<&'a str>) -> Vec<&'a str> {
+        args
+    }
+
+    /// Replace targets in the permission with their canonical names or ids, so rules can't be
+    /// bypassed by another way of naming the same target (ID prefix, range, part of the name).
+    /// Target matching several objects gives one permission per object, all of them must be allowed.
+    /// No permissions mean that the target couldn't be resolved and the update is denied.
+    async fn resolve(&self, permission: String) -> Vec<String> {
+        vec![permission]
+    }
+
+    /// Update is confirmed by the plugin itself (e.g. with a preview), so the `confirmation` guard doesn't ask again.
+    fn confirms(&self, _upd: &Update, _me: &Me) -> bool {
+        false
+    }
+}
+
+/// All plugins compiled into the bot. New plugins only need to be added here.
+pub fn registry() -> Vec<Box<dyn Plugin>> {
+    vec![
+        Box::new(system::SysPlugin::default()),
+        Box::new(transmission::TransmissionPlugin::default()),
+        Box::new(docker::DockerPlugin::default()),
+    ]
+}
+
+/// Permission of a message with one of the plugin commands: command name followed by normalized arguments.
+pub fn command_permission<P: Plugin + ?Sized>(plugin: &P, upd: &Update, me: &Me) -> Option<String> {
+    let UpdateKind::Message(msg) = &upd.kind else {
+        return None;
+    };
+    let (command, args) = parse_command(msg.text()?, me.username())?;
+    if !plugin.commands().iter().any(|known| known.command.trim_start_matches('/') == command) {
+        return None;
+    }
+    let mut segments = vec![command.to_string()];
+    segments.extend(plugin.action(args).into_iter().map(access::segment));
+    Some(segments.join("."))
+}
+
+/// Permission of the update among all enabled plugins.
+pub fn permission(plugins: &Plugins, upd: &Update, me: &Me) -> Option<String> {
+    plugins.iter().find_map(|plugin| plugin.permission(upd, me))
+}
+
+/// Permissions of the update with targets resolved by the plugin handling it, see `Plugin::resolve`.
+pub async fn permissions(plugins: &Plugins, upd: &Update, me: &Me) -> Option<Vec<String>> {
+    for plugin in plugins.iter() {
+        if let Some(permission) = plugin.permission(upd, me) {
+            return Some(plugin.resolve(permission).await);
+        }
+    }
+    None
+}
+
+/// Permissions of the update resolved once before it is dispatched, shared through dispatcher
+/// dependencies so access control, confirmation and the plugin handler agree on the targets.
+#[derive(Clone, Debug, Default)]
+pub struct Resolved(pub Option<Vec<String>>);
+
+/// Whether one of the plugins confirms the update itself, see `Plugin::confirms`.
+pub fn confirms(plugins: &Plugins, upd: &Update, me: &Me) -> bool {
+    plugins.iter().any(|plugin| plugin.confirms(upd, me))
+}
+
+/// Entry of the `plugins` config list: name of a built-in plugin or description of an external one.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PluginEntry {
+    Builtin(String),
+    External(external::ExternalConfig),
+}
+
+impl PluginEntry {
+    pub fn resolve(self) -> Result<Box<dyn Plugin>, String> {
+        match self {
+            PluginEntry::Builtin(name) => registry()
+                .into_iter()
+                .find(|plugin| plugin.name() == name)
+                .ok_or(format!("Plugin {} not found", name)),
+            PluginEntry::External(config) => Ok(Box::new(external::ExternalPlugin::new(config))),
+        }
+    }
+}
+
+/// Deserialize plugin config section, reporting the expected schema when it is missing or malformed.
+pub fn parse_config<T: DeserializeOwned>(plugin: &dyn Plugin, config: Option<&serde_yml::Value>) -> Result<T, String> {
+    let schema = plugin.config_schema().unwrap_or_default();
+    match config {
+        Some(value) => serde_yml::from_value(value.clone())
+            .map_err(|x| format!("{} config is invalid: {}\nExpected:\n{}", plugin.name(), x, schema)),
+        None => Err(format!("{} config is not present\nExpected:\n{}", plugin.name(), schema)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::me;
+
+    fn message(text: &str) -> Update {
+        crate::test_utils::message(1, -1, text)
+    }
+
+    fn permission_of(text: &str) -> Option<String> {
+        permission(&Arc::new(registry()), &message(text), &me())
+    }
+
+    #[test]
+    fn sys_aliases_are_normalized() {
+        assert_eq!(permission_of("/sys").as_deref(), Some("sys.system"));
+        assert_eq!(permission_of("/sys system").as_deref(), Some("sys.system"));
+        assert_eq!(permission_of("/sys network").as_deref(), Some("sys.net"));
+        assert_eq!(permission_of("/sys mount").as_deref(), Some("sys.disk"));
+        assert_eq!(permission_of("/sys ping").as_deref(), Some("sys.ping"));
+    }
+
+    #[test]
+    fn docker_aliases_are_normalized() {
+        assert_eq!(permission_of("/docker").as_deref(), Some("docker.info"));
+        assert_eq!(permission_of("/docker container").as_deref(), Some("docker.container.list"));
+        assert_eq!(permission_of("/docker compose").as_deref(), Some("docker.compose.list"));
+        assert_eq!(permission_of("/docker container det web").as_deref(), Some("docker.container.detail.web"));
+        assert_eq!(permission_of("/docker@captain_bot container kill web").as_deref(), Some("docker.container.kill.web"));
+    }
+
+    #[test]
+    fn transmission_defaults_to_list() {
+        assert_eq!(permission_of("/transmission").as_deref(), Some("transmission.list"));
+        assert_eq!(permission_of("/transmission remove 5 no").as_deref(), Some("transmission.remove.5.no"));
+        assert_eq!(permission_of("/transmission remove 5 YES").as_deref(), Some("transmission.remove.5.yes"));
+        assert_eq!(permission_of("/transmission remove 5 Y").as_deref(), Some("transmission.remove.5.y"));
+        assert_eq!(permission_of("/transmission remove 5 maybe").as_deref(), Some("transmission.remove.5.no"));
+    }
+
+    #[test]
+    fn dots_in_arguments_are_escaped() {
+        assert_eq!(permission_of("/docker container kill web.1").as_deref(), Some("docker.container.kill.web%2E1"));
+        assert_eq!(permission_of("/transmission stop ubuntu.iso").as_deref(), Some("transmission.stop.ubuntu%2Eiso"));
+    }
+
+    #[test]
+    fn unknown_commands_and_other_bots_have_no_permission() {
+        assert_eq!(permission_of("/unknown"), None);
+        assert_eq!(permission_of("/docker@other_bot"), None);
+        assert_eq!(permission_of("hello"), None);
+    }
+}
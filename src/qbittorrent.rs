@@ -0,0 +1,401 @@
+use async_trait::async_trait;
+use reqwest::{
+    header::{COOKIE, SET_COOKIE},
+    multipart::{Form, Part},
+    Client, RequestBuilder, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+use crate::torrent_client::{AddOptions, Added, Backend, TorrentClient, TorrentInfo, TorrentSource};
+
+/// qBittorrent WebUI API v2 backend.
+pub struct QBittorrent {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    client: Client,
+    /// `SID` cookie of the logged in session.
+    sid: Mutex<Option<String>>,
+    ids: std::sync::Mutex<Ids>,
+    /// File where ids are stored between restarts, so permissions given by id keep pointing to the same torrents.
+    ids_file: Option<String>,
+}
+
+/// qBittorrent has no torrent ids, so torrents are numbered in order they were added.
+/// Numbers are never reused, so removing a torrent doesn't shift others.
+#[derive(Default, Serialize, Deserialize)]
+struct Ids {
+    by_hash: HashMap<String, i64>,
+    last: i64,
+}
+
+impl Ids {
+    /// Ids of the torrents sorted by time they were added, forgets torrents that are gone.
+    fn assign(&mut self, hashes: &[&str]) -> Vec<i64> {
+        self.by_hash.retain(|hash, _| hashes.contains(&hash.as_str()));
+        hashes.iter().map(|hash| {
+            *self.by_hash.entry(hash.to_string()).or_insert_with(|| {
+                self.last += 1;
+                self.last
+            })
+        }).collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Torrent {
+    hash: String,
+    name: String,
+    state: String,
+    progress: f64,
+    added_on: i64,
+}
+
+impl QBittorrent {
+    pub fn new(url: &str, username: Option<String>, password: Option<String>, ids_file: Option<String>) -> Result<QBittorrent, String> {
+        let ids = match &ids_file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(data) => serde_json::from_str(&data).map_err(|x| format!("couldn't parse {}: {}", path, x))?,
+                Err(x) if x.kind() == std::io::ErrorKind::NotFound => Ids::default(),
+                Err(x) => return Err(format!("couldn't read {}: {}", path, x)),
+            },
+            None => Ids::default(),
+        };
+        Ok(QBittorrent {
+            url: url.trim_end_matches('/').to_string(),
+            username,
+            password,
+            client: Client::new(),
+            sid: Mutex::new(None),
+            ids: std::sync::Mutex::new(ids),
+            ids_file,
+        })
+    }
+
+    /// Ids of the torrents, saved to `ids_file` when torrents were added or removed.
+    fn assign_ids(&self, hashes: &[&str]) -> Vec<i64> {
+        let mut ids = self.ids.lock().unwrap();
+        let before = (ids.by_hash.len(), ids.last);
+        let assigned = ids.assign(hashes);
+        if let Some(path) = self.ids_file.as_ref().filter(|_| (ids.by_hash.len(), ids.last) != before) {
+            let result = serde_json::to_string(&*ids)
+                .map_err(|x| x.to_string())
+                .and_then(|data| std::fs::write(path, data).map_err(|x| x.to_string()));
+            if let Err(x) = result {
+                log::error!("Failed to save torrent ids {}: {}", path, x);
+            }
+        }
+        assigned
+    }
+
+    /// Log in and return session cookie. Without credentials WebUI must allow access without login,
+    /// e.g. with "Bypass authentication for clients on localhost".
+    async fn login(&self) -> Result<Option<String>, String> {
+        let (Some(username), Some(password)) = (&self.username, &self.password) else {
+            return Ok(None);
+        };
+        let rsp = self.client.post(format!("{}/api/v2/auth/login", self.url))
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await
+            .map_err(|x| x.to_string())?;
+        let sid = rsp.headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .find_map(|cookie| cookie.strip_prefix("SID="))
+            .map(|cookie| cookie.split(';').next().unwrap_or_default().to_string());
+        let body = rsp.text().await.map_err(|x| x.to_string())?;
+        match sid {
+            Some(sid) if body == "Ok." => Ok(Some(sid)),
+            _ => Err(format!("qBittorrent login failed: {}", body)),
+        }
+    }
+
+    /// Send request with session cookie, logs in again when session expired.
+    async fn request(&self, build: impl Fn(&Client) -> RequestBuilder) -> Result<Response, String> {
+        for attempt in 0..2 {
+            let sid = {
+                let mut sid = self.sid.lock().await;
+                if sid.is_none() || attempt > 0 {
+                    *sid = self.login().await?;
+                }
+                sid.clone()
+            };
+            let mut request = build(&self.client);
+            if let Some(sid) = sid {
+                request = request.header(COOKIE, format!("SID={}", sid));
+            }
+            let rsp = request.send().await.map_err(|x| x.to_string())?;
+            if rsp.status() != StatusCode::FORBIDDEN {
+                return Ok(rsp);
+            }
+        }
+        Err("qBittorrent rejected credentials".to_string())
+    }
+
+    /// Post torrent hashes to the action endpoint. `fallback` is used by qBittorrent 5,
+    /// that renamed pause and resume to stop and start.
+    async fn action(&self, endpoint: &str, fallback: Option<&str>, torrents: &[TorrentInfo], params: &[(&str, &str)]) -> Result<(), String> {
+        let hashes = torrents.iter().map(|torrent| torrent.hash.as_str()).collect::<Vec<&str>>().join("|");
+        let mut form = vec![("hashes", hashes.as_str())];
+        form.extend_from_slice(params);
+        let mut rsp = self.request(|client| client.post(format!("{}/api/v2/torrents/{}", self.url, endpoint)).form(&form)).await?;
+        if let (StatusCode::NOT_FOUND, Some(fallback)) = (rsp.status(), fallback) {
+            rsp = self.request(|client| client.post(format!("{}/api/v2/torrents/{}", self.url, fallback)).form(&form)).await?;
+        }
+        rsp.error_for_status().map(|_| ()).map_err(|x| x.to_string())
+    }
+}
+
+#[async_trait]
+impl TorrentClient for QBittorrent {
+    fn backend(&self) -> Backend {
+        Backend::QBittorrent
+    }
+
+    async fn list(&self) -> Result<Vec<TorrentInfo>, String> {
+        let mut torrents: Vec<Torrent> = self.request(|client| client.get(format!("{}/api/v2/torrents/info", self.url)))
+            .await?
+            .error_for_status()
+            .map_err(|x| x.to_string())?
+            .json()
+            .await
+            .map_err(|x| x.to_string())?;
+        torrents.sort_by(|a, b| (a.added_on, &a.hash).cmp(&(b.added_on, &b.hash)));
+        let ids = self.assign_ids(&torrents.iter().map(|torrent| torrent.hash.as_str()).collect::<Vec<&str>>());
+        Ok(torrents.into_iter().zip(ids).map(|(torrent, id)| TorrentInfo {
+            id,
+            stalled: torrent.state == "stalledDL",
+            hash: torrent.hash,
+            name: torrent.name,
+            status: torrent.state,
+            percent_done: torrent.progress,
+        }).collect())
+    }
+
+    async fn add(&self, source: TorrentSource, options: AddOptions) -> Result<Added, String> {
+        let form = || {
+            let mut form = match &source {
+                TorrentSource::File(data) => Form::new().part("torrents", Part::bytes(data.clone()).file_name("file.torrent")),
+                TorrentSource::Link(link) => Form::new().text("urls", link.clone()),
+            };
+            if let Some(download_dir) = &options.download_dir {
+                form = form.text("savepath", download_dir.clone());
+            }
+            if !options.labels.is_empty() {
+                form = form.text("tags", options.labels.join(","));
+            }
+            if options.paused {
+                // `paused` was renamed to `stopped` in qBittorrent 5
+                form = form.text("paused", "true").text("stopped", "true");
+            }
+            form
+        };
+        let body = self.request(|client| client.post(format!("{}/api/v2/torrents/add", self.url)).multipart(form()))
+            .await?
+            .error_for_status()
+            .map_err(|x| x.to_string())?
+            .text()
+            .await
+            .map_err(|x| x.to_string())?;
+        if body == "Fails." {
+            return Err("qBittorrent couldn't add torrent".to_string());
+        }
+        // qBittorrent doesn't return added torrent, hash is known only for magnet links
+        let hash = match &source {
+            TorrentSource::Link(link) => magnet_hash(link),
+            TorrentSource::File(_) => None,
+        };
+        Ok(Added {
+            hash,
+            name: None,
+            duplicate: false,
+        })
+    }
+
+    async fn start(&self, torrents: &[TorrentInfo]) -> Result<(), String> {
+        self.action("resume", Some("start"), torrents, &[]).await
+    }
+
+    async fn stop(&self, torrents: &[TorrentInfo]) -> Result<(), String> {
+        self.action("pause", Some("stop"), torrents, &[]).await
+    }
+
+    async fn remove(&self, torrents: &[TorrentInfo], with_data: bool) -> Result<(), String> {
+        self.action("delete", None, torrents, &[("deleteFiles", if with_data { "true" } else { "false" })]).await
+    }
+}
+
+/// Info hash of magnet link in lowercase hex as qBittorrent reports it. Base32 hashes are converted to hex.
+fn magnet_hash(link: &str) -> Option<String> {
+    let hash = link.split(['?', '&'])
+        .find_map(|param| param.strip_prefix("xt=urn:btih:"))?;
+    match hash.len() {
+        40 if hash.chars().all(|char| char.is_ascii_hexdigit()) => Some(hash.to_lowercase()),
+        32 => {
+            let mut bits: u64 = 0;
+            let mut count = 0;
+            let mut hex = String::new();
+            for char in hash.chars() {
+                let value = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567".find(char.to_ascii_uppercase())?;
+                bits = (bits << 5) | value as u64;
+                count += 5;
+                if count >= 8 {
+                    count -= 8;
+                    hex += &format!("{:02x}", (bits >> count) & 0xff);
+                }
+            }
+            Some(hex)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Serve one canned response per connection, returns WebUI url and requests seen as
+    /// `METHOD /path` followed by the cookie header and body.
+    fn serve(responses: Vec<&'static str>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut request = line.split_whitespace().take(2).collect::<Vec<&str>>().join(" ");
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(": ").unwrap_or_default();
+                    match name.to_lowercase().as_str() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "cookie" => request += &format!(" {}", value),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                if !body.is_empty() {
+                    request += &format!(" {}", String::from_utf8_lossy(&body));
+                }
+                requests.push(request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    const LOGIN_A: &str = "HTTP/1.1 200 OK\r\nSet-Cookie: SID=a; HttpOnly; path=/\r\nContent-Length: 3\r\nConnection: close\r\n\r\nOk.";
+    const LOGIN_B: &str = "HTTP/1.1 200 OK\r\nSet-Cookie: SID=b; HttpOnly; path=/\r\nContent-Length: 3\r\nConnection: close\r\n\r\nOk.";
+    const FORBIDDEN: &str = "HTTP/1.1 403 Forbidden\r\nContent-Length: 9\r\nConnection: close\r\n\r\nForbidden";
+    const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const TWO_TORRENTS: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 157\r\nConnection: close\r\n\r\n\
+        [{\"hash\":\"bb\",\"name\":\"Debian\",\"state\":\"stalledDL\",\"progress\":0.5,\"added_on\":2},\
+        {\"hash\":\"aa\",\"name\":\"Ubuntu\",\"state\":\"uploading\",\"progress\":1.0,\"added_on\":1}]";
+    const ONE_TORRENT: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 79\r\nConnection: close\r\n\r\n\
+        [{\"hash\":\"bb\",\"name\":\"Debian\",\"state\":\"stalledDL\",\"progress\":0.5,\"added_on\":2}]";
+
+    fn torrent(hash: &str) -> TorrentInfo {
+        TorrentInfo {
+            id: 1,
+            hash: hash.to_string(),
+            name: String::new(),
+            status: String::new(),
+            percent_done: 0.0,
+            stalled: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_session_logs_in_again() {
+        let (url, server) = serve(vec![LOGIN_A, OK, FORBIDDEN, LOGIN_B, OK]);
+        let client = QBittorrent::new(&url, Some("admin".to_string()), Some("secret".to_string()), None).unwrap();
+        client.start(&[torrent("aa")]).await.unwrap();
+        client.start(&[torrent("bb")]).await.unwrap();
+        assert_eq!(server.join().unwrap(), [
+            "POST /api/v2/auth/login username=admin&password=secret",
+            "POST /api/v2/torrents/resume SID=a hashes=aa",
+            "POST /api/v2/torrents/resume SID=a hashes=bb",
+            "POST /api/v2/auth/login username=admin&password=secret",
+            "POST /api/v2/torrents/resume SID=b hashes=bb",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn rejected_login_is_reported() {
+        let (url, server) = serve(vec![FORBIDDEN]);
+        let client = QBittorrent::new(&url, Some("admin".to_string()), Some("wrong".to_string()), None).unwrap();
+        assert_eq!(client.list().await.err().as_deref(), Some("qBittorrent login failed: Forbidden"));
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pause_falls_back_to_stop() {
+        let (url, server) = serve(vec![NOT_FOUND, OK]);
+        let client = QBittorrent::new(&url, None, None, None).unwrap();
+        client.stop(&[torrent("aa"), torrent("bb")]).await.unwrap();
+        assert_eq!(server.join().unwrap(), [
+            "POST /api/v2/torrents/pause hashes=aa%7Cbb",
+            "POST /api/v2/torrents/stop hashes=aa%7Cbb",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn ids_are_kept_when_torrents_are_removed() {
+        let (url, server) = serve(vec![TWO_TORRENTS, ONE_TORRENT, TWO_TORRENTS]);
+        let client = QBittorrent::new(&url, None, None, None).unwrap();
+        let ids = |torrents: Vec<TorrentInfo>| torrents.iter().map(|torrent| (torrent.id, torrent.name.clone(), torrent.stalled)).collect::<Vec<_>>();
+        assert_eq!(ids(client.list().await.unwrap()), [(1, "Ubuntu".to_string(), false), (2, "Debian".to_string(), true)]);
+        assert_eq!(ids(client.list().await.unwrap()), [(2, "Debian".to_string(), true)]);
+        // Torrent added again gets a new id
+        assert_eq!(ids(client.list().await.unwrap()), [(3, "Ubuntu".to_string(), false), (2, "Debian".to_string(), true)]);
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn ids_are_kept_between_restarts() {
+        let path = std::env::temp_dir().join(format!("tg-captain-ids-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ids_file = Some(path.to_string_lossy().to_string());
+        let (url, server) = serve(vec![TWO_TORRENTS, ONE_TORRENT]);
+        let client = QBittorrent::new(&url, None, None, ids_file.clone()).unwrap();
+        assert_eq!(client.list().await.unwrap().iter().map(|torrent| torrent.id).collect::<Vec<i64>>(), [1, 2]);
+        // Restarted bot keeps ids of known torrents and doesn't reuse ids of removed ones
+        let client = QBittorrent::new(&url, None, None, ids_file.clone()).unwrap();
+        assert_eq!(client.list().await.unwrap().iter().map(|torrent| torrent.id).collect::<Vec<i64>>(), [2]);
+        server.join().unwrap();
+        let (url, server) = serve(vec![TWO_TORRENTS]);
+        let client = QBittorrent::new(&url, None, None, ids_file).unwrap();
+        assert_eq!(client.list().await.unwrap().iter().map(|torrent| torrent.id).collect::<Vec<i64>>(), [3, 2]);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn magnet_hash_is_hex() {
+        assert_eq!(magnet_hash("magnet:?xt=urn:btih:C12FE1C06BBA254A9DC9F519B335AA7C1367A88A&dn=ubuntu").as_deref(),
+            Some("c12fe1c06bba254a9dc9f519b335aa7c1367a88a"));
+        assert_eq!(magnet_hash("magnet:?dn=ubuntu&xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").as_deref(),
+            Some("c12fe1c06bba254a9dc9f519b335aa7c1367a88a"));
+        assert_eq!(magnet_hash("magnet:?xt=urn:btih:abc"), None);
+        assert_eq!(magnet_hash("https://example.com/file.torrent"), None);
+    }
+}
@@ -1,52 +1,130 @@
 use teloxide::{
-    dispatching::DpHandlerDescription,
     prelude::*,
-    utils::command::BotCommands, RequestError,
+    types::BotCommand,
+    utils::command::BotCommands,
 };
 use sysinfo::{
     Components, Disks, Networks, System,
 };
+use serde::Deserialize;
 use std::collections::HashMap;
-use httping::ping;
+
+use crate::audit;
+use crate::monitor::{self, MonitorConfig};
+use crate::plugin::{parse_config, Plugin, UpdateHandler};
+use crate::uptime::{self, Services, UptimeConfig};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command{
+    #[command(description = "system info and power management")]
     Sys(String)
 }
 
-pub fn get_short_help()-> String{
-    return "Sys plugin. Usage /sys [mode]. For detail help /sys help".to_string();
+#[derive(Deserialize, Debug)]
+struct Sys {
+    ping: HashMap<String, String>,
+    monitor: Option<MonitorConfig>,
+    uptime: Option<UptimeConfig>,
 }
 
-pub fn get_update_handler(host_info: HashMap<String, String>) -> Handler<'static, DependencyMap, Result<(), RequestError>, DpHandlerDescription> {
-    let answer_closure = move |bot, msg, cmd| {
-        answer(bot, msg, cmd, host_info.clone())
-    };
-    Update::filter_message()
-        .branch(
-            dptree::entry()
-                .filter_command::<Command>()
-                .endpoint(answer_closure),
-        )
+#[derive(Default)]
+pub struct SysPlugin {
+    services: Services,
+}
+
+impl Plugin for SysPlugin {
+    fn name(&self) -> &str {
+        "sys"
+    }
+
+    fn config_schema(&self) -> Option<&'static str> {
+        Some(r#"sys:
+  ping:
+    <Service name>: <http or https>:<ip>:<port>
+  monitor: #optional
+    interval: <seconds between samples>
+    chats: [<chat-id>]
+    hysteresis: <how much value must drop below threshold to recover>
+    cpu: {above: <percent>, minutes: <how long load must stay above>}
+    ram: {above: <percent>}
+    disks: {<mount point or *>: {above: <percent>}}
+    temperature: {<component label or *>: {above: <°C>}}
+  uptime: #optional
+    interval: <seconds between checks>
+    chats: [<chat-id>]"#)
+    }
+
+    fn short_help(&self) -> String {
+        "Sys plugin. Usage /sys [mode]. For detail help /sys help".to_string()
+    }
+
+    fn help(&self) -> String {
+        get_info_help_text()
+    }
+
+    fn commands(&self) -> Vec<BotCommand> {
+        Command::bot_commands()
+    }
+
+    fn action<'a>(&self, mut args: Vec<&'a str>) -> Vec<&'a str> {
+        match args.first().copied() {
+            None | Some("system") => args = vec!["system"],
+            Some("network") => args[0] = "net",
+            Some("mount") => args[0] = "disk",
+            _ => {}
+        }
+        args
+    }
+
+    fn update_handler(&self, config: Option<&serde_yml::Value>) -> Result<UpdateHandler, String> {
+        let config: Sys = parse_config(self, config)?;
+        let host_info = config.ping;
+        let services = config.uptime.map(|_| self.services.clone());
+        let answer_closure = move |bot, msg, cmd| {
+            answer(bot, msg, cmd, host_info.clone(), services.clone())
+        };
+        Ok(Update::filter_message()
+            .branch(
+                dptree::entry()
+                    .filter_command::<Command>()
+                    .endpoint(answer_closure),
+            ))
+    }
+
+    fn start(&self, bot: Bot, config: Option<&serde_yml::Value>) -> Result<(), String> {
+        let config: Sys = parse_config(self, config)?;
+        if let Some(monitor_config) = config.monitor {
+            monitor::start(bot.clone(), monitor_config)?;
+        }
+        if let Some(uptime_config) = config.uptime {
+            uptime::start(bot, self.services.clone(), config.ping, uptime_config)?;
+        }
+        Ok(())
+    }
 }
 
 async fn answer(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    host_info: HashMap<String, String>) -> ResponseResult<()> {
+    host_info: HashMap<String, String>,
+    services: Option<Services>) -> ResponseResult<()> {
     match cmd {
         Command::Sys(mode) => {
-            if mode == "system" || mode == ""{
+            if mode == "system" || mode.is_empty(){
                 bot.send_message(msg.chat.id, get_system_info().await).await?;
             } else if mode =="net" || mode == "network"{
                 bot.send_message(msg.chat.id, get_network_info()).await?;
             } else if mode =="disk" || mode == "mount"{
                 bot.send_message(msg.chat.id, get_disk_info()).await?;
             } else if mode =="ping"{
-                bot.send_message(msg.chat.id, "FETCHING...").await?;
-                bot.send_message(msg.chat.id, get_host_info(host_info).await).await?;
+                if let Some(services) = services {
+                    bot.send_message(msg.chat.id, uptime::report(&services)).await?;
+                } else {
+                    bot.send_message(msg.chat.id, "FETCHING...").await?;
+                    bot.send_message(msg.chat.id, get_host_info(host_info).await).await?;
+                }
             } else if mode == "temp"{
                 bot.send_message(msg.chat.id, get_temp_info()).await?;
             } else if mode == "shutdown"{
@@ -85,12 +163,12 @@ fn get_network_info() -> String{
     for (interface_name, data) in &networks {
         networks_stat += format!(
             "{interface_name}: {:.3} MB (down) / {:.3} MB (up)\n",
-            (data.total_received() as f32)*0.00000095367432,
-            (data.total_transmitted() as f32)*0.00000095367432,
+            (data.total_received() as f32)/1048576.0,
+            (data.total_transmitted() as f32)/1048576.0,
         ).as_str();
 
     }
-    format!("{}", networks_stat)
+    networks_stat
 }
 
 fn get_disk_info() -> String{
@@ -100,22 +178,19 @@ fn get_disk_info() -> String{
         mounts_data += format!("{:?}--{:?}-->{:?} {:.2}/{:.2} Gb\n", disk.name(), disk.file_system(), disk.mount_point(), (disk.available_space() as f32)/1073741824.0, (disk.total_space() as f32)/1073741824.0).as_str();
     }
 
-    format!("{}", mounts_data)
+    mounts_data
 }   
 
 async fn get_host_info(services : HashMap<String, String>) -> String{
     let mut data = String::new();
     for (key, value) in services.into_iter() {
-        let addr: Vec<&str> = value.split(":").collect();
-        
-        match ping("", addr[1], addr[0],addr[2].parse().unwrap(),).await{
-            Ok(_) => data += format!("{}: OK\n", key).as_str(),
+        match uptime::check(&value).await{
+            Ok(latency) => data += format!("{}: OK ({} ms)\n", key, latency).as_str(),
             Err(_) => data += format!("{}: BAD\n", key).as_str()
         }
     }
 
-
-    return data;
+    data
 }
 
 fn get_temp_info() -> String{
@@ -124,43 +199,43 @@ fn get_temp_info() -> String{
     for component in &components {
         temps += format!("{component:?}\n").as_str();
     }
-    if temps == ""{
-        return "N/A".to_string();
+    if temps.is_empty(){
+        "N/A".to_string()
     } else {
-        return temps;
+        temps
     }
 }
 
 fn shutdown() -> String {
     match system_shutdown::shutdown() {
         Ok(_) => "Shutting down".to_string(),
-        Err(error) => format!("Failed to shut down: {}", error),
+        Err(error) => audit::failure(format!("Failed to shut down: {}", error)),
     }
 }
 
 fn reboot() -> String {
     match system_shutdown::reboot() {
         Ok(_) => "Rebooting".to_string(),
-        Err(error) => format!("Failed to reboot: {}", error),
+        Err(error) => audit::failure(format!("Failed to reboot: {}", error)),
     }
 }
 
 fn sleep() -> String {
     match system_shutdown::sleep() {
         Ok(_) => "Switching to sleep mode".to_string(),
-        Err(error) => format!("Failed to sleep: {}", error),
+        Err(error) => audit::failure(format!("Failed to sleep: {}", error)),
     }
 }
 
 fn hibernate() -> String {
     match system_shutdown::hibernate() {
         Ok(_) => "Hibernating".to_string(),
-        Err(error) => format!("Failed to hibernate: {}", error),
+        Err(error) => audit::failure(format!("Failed to hibernate: {}", error)),
     }
 }
 
 fn get_info_help_text() -> String {
-    let help_text = r#"
+    r#"
 Info Command Usage:
 
 /sys [mode]
@@ -177,7 +252,7 @@ disk or mount
   Provides information about mounted disk partitions, file systems, and disk usage.
 
 ping
-  Fetches and displays the host information, which may include details like hostname, IP address, etc.
+  Checks services from the config and shows their state and latency. When uptime monitor is enabled shows state of the last check, latency and uptime percentage.
 
 temp
   Retrieves and shows the current temperature readings for the system.
@@ -197,7 +272,5 @@ hibernate
 If no mode is specified or an invalid mode is provided, the command will display the system information by default.
 
 Note: The 'shutdown', 'reboot', 'sleep', and 'hibernate' modes require appropriate permissions to execute successfully.
-"#.to_string();
-
-    help_text
+"#.to_string()
 }
\ No newline at end of file
@@ -0,0 +1,36 @@
+//! Builders of telegram objects shared by unit tests.
+
+use teloxide::types::{Me, Update};
+
+/// Update with text message sent by `user`, negative `chat` is a group and positive one is a private chat.
+pub fn message(user: u64, chat: i64, text: &str) -> Update {
+    let chat = if chat < 0 {
+        serde_json::json!({"id": chat, "type": "group", "title": "group"})
+    } else {
+        serde_json::json!({"id": chat, "type": "private", "first_name": "user"})
+    };
+    // Built from string, `from_value` can't deserialize update kind
+    serde_json::from_str(&serde_json::json!({
+        "update_id": 1,
+        "message": {
+            "message_id": 1,
+            "date": 0,
+            "chat": chat,
+            "from": {"id": user, "is_bot": false, "first_name": "user", "username": "user"},
+            "text": text
+        }
+    }).to_string()).unwrap()
+}
+
+/// The bot itself, its username is `captain_bot`.
+pub fn me() -> Me {
+    serde_json::from_value(serde_json::json!({
+        "id": 2,
+        "is_bot": true,
+        "first_name": "captain",
+        "username": "captain_bot",
+        "can_join_groups": true,
+        "can_read_all_group_messages": false,
+        "supports_inline_queries": false
+    })).unwrap()
+}
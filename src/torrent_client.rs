@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// Torrent client the `/transmission` commands are sent to, selected by `transmission.backend`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Transmission,
+    QBittorrent,
+}
+
+/// Torrent as shown by `/transmission list` and matched by selectors.
+pub struct TorrentInfo {
+    /// Transmission torrent id. qBittorrent has no ids, so torrents are numbered by the client, see `qbittorrent::Ids`.
+    pub id: i64,
+    pub hash: String,
+    pub name: String,
+    pub status: String,
+    pub percent_done: f64,
+    pub stalled: bool,
+}
+
+pub enum TorrentSource {
+    /// Content of .torrent file.
+    File(Vec<u8>),
+    /// Magnet link or url of .torrent file.
+    Link(String),
+}
+
+#[derive(Default)]
+pub struct AddOptions {
+    pub download_dir: Option<String>,
+    pub paused: bool,
+    pub labels: Vec<String>,
+}
+
+pub struct Added {
+    pub hash: Option<String>,
+    pub name: Option<String>,
+    pub duplicate: bool,
+}
+
+/// Operations supported by every torrent backend.
+#[async_trait]
+pub trait TorrentClient: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    async fn list(&self) -> Result<Vec<TorrentInfo>, String>;
+
+    async fn add(&self, source: TorrentSource, options: AddOptions) -> Result<Added, String>;
+
+    async fn start(&self, torrents: &[TorrentInfo]) -> Result<(), String>;
+
+    async fn stop(&self, torrents: &[TorrentInfo]) -> Result<(), String>;
+
+    async fn remove(&self, torrents: &[TorrentInfo], with_data: bool) -> Result<(), String>;
+}
@@ -1,50 +1,400 @@
 use teloxide::{
+    net::Download,
     prelude::*,
-    types::{ MessageKind, MediaKind},
-    dispatching::DpHandlerDescription,
-    utils::command::BotCommands, RequestError,
+    types::{ BotCommand, InlineKeyboardButton, InlineKeyboardMarkup, Me, MessageKind, MediaKind, UpdateKind},
+    utils::command::{parse_command, BotCommands},
 };
 use transmission_rpc::types;
 use transmission_rpc::TransClient;
 use substring::Substring;
+use base64::Engine;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use crate::access::{segment, unescape};
+use crate::audit;
+use crate::confirm::Confirmation;
+use crate::plugin::{command_permission, parse_config, Plugin, Resolved, UpdateHandler};
+use crate::qbittorrent::QBittorrent;
+use crate::torrent_client::{AddOptions, Added, Backend, TorrentClient, TorrentInfo, TorrentSource};
+use crate::utils::{format_duration, format_size, split_message};
 
 extern crate mime;
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command{
+    #[command(description = "manage transmission torrents")]
     Transmission (String)
 }
 
-pub fn get_short_help()-> String{
-    return "Transsmision plugin. Usage /transmission [mode]. For detail help /transmission help".to_string();
+#[derive(Deserialize, Debug)]
+struct Transmission {
+    #[serde(default)]
+    backend: Backend,
+    /// Transmission rpc url or qBittorrent WebUI url.
+    rpc: String,
+    /// qBittorrent WebUI credentials.
+    username: Option<String>,
+    password: Option<String>,
+    /// File where qBittorrent torrent ids are stored between restarts.
+    ids_file: Option<String>,
+    notify: Option<NotifyConfig>,
+    /// Download directory and labels of dropped .torrent files by message caption.
+    #[serde(default)]
+    categories: HashMap<String, Category>,
 }
 
-pub fn get_update_handler(url: &String) -> Handler<'static, DependencyMap, Result<(), RequestError>, DpHandlerDescription>{
-    let url_clone1 = url.to_owned();
-    let url_clone2 = url.to_owned();
+#[derive(Deserialize, Debug, Clone)]
+struct Category {
+    download_dir: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
 
-    let file_closure = move |bot, msg| {
-        add_file(bot, msg, url_clone1.clone())
-    };
-    let command_closure = move |bot, msg, cmd: Command| {
-        command_handler(bot, msg, cmd, url_clone2.clone())
-    };
-    Update::filter_message()
-    .branch(
-        dptree::entry()
-            .filter_command::<Command>()
-            .endpoint(command_closure),
-    )
-    .branch(
-        dptree::entry()
-        .filter(|msg: Message| file_filter(msg))
-        .endpoint(file_closure)
-    )
+type Categories = Arc<HashMap<String, Category>>;
+
+/// `transmission.notify` config section.
+#[derive(Deserialize, Debug, Clone)]
+struct NotifyConfig {
+    /// Seconds between polls.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Chats notified about torrents that weren't added from telegram.
+    #[serde(default)]
+    chats: Vec<i64>,
+    /// Minutes torrent must be stalled before it is reported.
+    #[serde(default = "default_stall_minutes")]
+    stall_minutes: u64,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_stall_minutes() -> u64 {
+    30
+}
+
+/// Chats that added torrents by torrent hash, used to route notifications.
+type Owners = Arc<Mutex<HashMap<String, ChatId>>>;
+
+/// Dropped .torrent files with unknown caption waiting for category to be chosen.
+#[derive(Default)]
+struct Uploads {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, PendingUpload>>,
+}
+
+struct PendingUpload {
+    user: Option<UserId>,
+    data: Vec<u8>,
+    created: Instant,
+}
+
+/// Seconds the category of dropped file can be chosen.
+const UPLOAD_TIMEOUT: u64 = 300;
+
+/// State shared by the handlers built in `update_handler`.
+#[derive(Clone)]
+struct Context {
+    client: Arc<dyn TorrentClient>,
+    owners: Owners,
+    categories: Categories,
+    uploads: Arc<Uploads>,
+    /// Commands beyond TorrentClient use transmission rpc directly, they aren't available with other backends.
+    rpc: Option<reqwest::Url>,
+}
+
+#[derive(Default)]
+pub struct TransmissionPlugin {
+    owners: Owners,
+    uploads: Arc<Uploads>,
+    /// Client used to resolve selectors in permissions, set by `update_handler`.
+    client: OnceLock<Arc<dyn TorrentClient>>,
+}
+
+#[async_trait]
+impl Plugin for TransmissionPlugin {
+    fn name(&self) -> &str {
+        "transmission"
+    }
+
+    fn config_schema(&self) -> Option<&'static str> {
+        Some(r#"transmission:
+  backend: <transmission or qbittorrent>
+  rpc: <http or https>://<url>/transmission/rpc or <http or https>://<qbittorrent webui url>
+  username: <qbittorrent webui user>
+  password: <qbittorrent webui password>
+  ids_file: <path to file with qbittorrent torrent ids>
+  categories:
+    <caption>:
+      download_dir: <path>
+      labels: [<label>]
+  notify:
+    interval: 60
+    chats: [<chat-id>]
+    stall_minutes: 30"#)
+    }
+
+    fn short_help(&self) -> String {
+        "Transsmision plugin. Usage /transmission [mode]. For detail help /transmission help".to_string()
+    }
+
+    fn help(&self) -> String {
+        get_command_handler_help_text()
+    }
+
+    fn commands(&self) -> Vec<BotCommand> {
+        Command::bot_commands()
+    }
+
+    fn permission(&self, upd: &Update, me: &Me) -> Option<String> {
+        match &upd.kind {
+            UpdateKind::Message(msg) if file_filter(msg) || magnet_filter(msg) => Some("transmission.add".to_string()),
+            // Buttons of `/transmission files <id>` require the same permission as the command
+            UpdateKind::CallbackQuery(query) => query.data.as_ref()
+                .and_then(|data| data.strip_prefix("tr:files:"))
+                .and_then(|data| data.split(':').next())
+                .map(|id| format!("transmission.files.{}", id))
+                .or(query.data.as_ref().filter(|data| data.starts_with("tr:category:")).map(|_| "transmission.add".to_string())),
+            _ => command_permission(self, upd, me),
+        }
+    }
+
+    fn action<'a>(&self, mut args: Vec<&'a str>) -> Vec<&'a str> {
+        if args.is_empty() {
+            return vec!["list"];
+        }
+        if args[0] == "remove" && args.len() >= 3 {
+            let last = args.len() - 1;
+            args[last] = removal_flag(args[last]);
+        }
+        args
+    }
+
+    /// Bulk removals are previewed with their own keyboard, see `previews_removal`.
+    fn confirms(&self, upd: &Update, me: &Me) -> bool {
+        let UpdateKind::Message(msg) = &upd.kind else {
+            return false;
+        };
+        msg.text()
+            .and_then(|text| parse_command(text, me.username()))
+            .is_some_and(|(command, args)| command == "transmission" && previews_removal(&args))
+    }
+
+    fn update_handler(&self, config: Option<&serde_yml::Value>) -> Result<UpdateHandler, String> {
+        let config: Transmission = parse_config(self, config)?;
+        let client = torrent_client(&config)?;
+        let _ = self.client.set(client.clone());
+        let context = Context {
+            client,
+            owners: self.owners.clone(),
+            categories: Arc::new(config.categories.iter()
+                .map(|(name, category)| (name.to_lowercase(), category.clone()))
+                .collect()),
+            uploads: self.uploads.clone(),
+            rpc: match config.backend {
+                Backend::Transmission => Some(config.rpc.parse::<reqwest::Url>().map_err(|x| format!("invalid transmission.rpc {}: {}", config.rpc, x))?),
+                _ => None,
+            },
+        };
+        let file_context = context.clone();
+        let file_closure = move |bot, msg| {
+            add_file(bot, msg, file_context.clone())
+        };
+        let category_context = context.clone();
+        let category_closure = move |bot, query| {
+            category_callback(bot, query, category_context.clone())
+        };
+        let magnet_context = context.clone();
+        let magnet_closure = move |bot, msg| {
+            add_magnet(bot, msg, magnet_context.clone())
+        };
+        let rpc = context.rpc.clone();
+        let command_closure = move |bot, cmd: Command, upd: Update, me: Me, resolved: Resolved, confirmation: Arc<Confirmation>| {
+            command_handler(bot, cmd, upd, me, resolved, context.clone(), confirmation)
+        };
+        let mut handler = dptree::entry();
+        if let Some(url) = rpc {
+            let files_closure = move |bot, query| {
+                files_callback(bot, query, url.clone())
+            };
+            handler = handler.branch(
+                Update::filter_callback_query()
+                .filter(|query: CallbackQuery| query.data.as_ref().is_some_and(|data| data.starts_with("tr:files:")))
+                .endpoint(files_closure)
+            );
+        }
+        Ok(handler
+        .branch(
+            Update::filter_callback_query()
+            .filter(|query: CallbackQuery| query.data.as_ref().is_some_and(|data| data.starts_with("tr:category:")))
+            .endpoint(category_closure)
+        )
+        .branch(Update::filter_message()
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(command_closure),
+        )
+        .branch(
+            dptree::entry()
+            .filter(|msg: Message| file_filter(&msg))
+            .endpoint(file_closure)
+        )
+        .branch(
+            dptree::entry()
+            .filter(|msg: Message| magnet_filter(&msg))
+            .endpoint(magnet_closure)
+        )))
+    }
+
+    /// Selector is replaced with ids of matched torrents, one permission per torrent, e.g.
+    /// `transmission.remove.5-6.no` becomes `transmission.remove.5.no` and `transmission.remove.6.no`.
+    async fn resolve(&self, permission: String) -> Vec<String> {
+        let segments: Vec<&str> = permission.split('.').collect();
+        let Some(client) = self.client.get() else {
+            return vec![permission];
+        };
+        let Some(targets) = selector_segments(&segments[1..]).map(|range| range.start + 1..range.end + 1) else {
+            return vec![permission];
+        };
+        let selector = segments[targets.clone()].iter().map(|target| unescape(target)).collect::<Vec<String>>().join(" ");
+        let ids: Vec<String> = match segments[1] {
+            // Only a single id is accepted by these commands, anything else is rejected by the handler
+            "info" | "files" => match selector.parse::<i64>() {
+                Ok(id) => vec![id.to_string()],
+                Err(_) => return vec![permission],
+            },
+            // Selector that can't be resolved is denied, the handler acts only on resolved torrents
+            _ => select_torrents(client.as_ref(), &selector).await
+                .map(|torrents| torrents.iter().map(|torrent| torrent.id.to_string()).collect())
+                .unwrap_or_default(),
+        };
+        ids.into_iter()
+            .map(|id| [&segments[..targets.start], &[segment(&id).as_str()], &segments[targets.end..]].concat().join("."))
+            .collect()
+    }
+
+    fn start(&self, bot: Bot, config: Option<&serde_yml::Value>) -> Result<(), String> {
+        let config: Transmission = parse_config(self, config)?;
+        match config.notify {
+            Some(_) if config.backend != Backend::Transmission => Err("transmission.notify is only supported by transmission backend".to_string()),
+            Some(notify) => start_notifier(bot, &config.rpc, self.owners.clone(), notify),
+            None => Ok(()),
+        }
+    }
+}
+
+fn torrent_client(config: &Transmission) -> Result<Arc<dyn TorrentClient>, String> {
+    let url: reqwest::Url = config.rpc.parse().map_err(|x| format!("invalid transmission.rpc {}: {}", config.rpc, x))?;
+    match config.backend {
+        Backend::Transmission => Ok(Arc::new(TransmissionClient { url })),
+        // Ids are assigned by the bot, permissions given by id must point to the same torrents after restart
+        Backend::QBittorrent => match &config.ids_file {
+            Some(ids_file) => Ok(Arc::new(QBittorrent::new(&config.rpc, config.username.clone(), config.password.clone(), Some(ids_file.clone()))?)),
+            None => Err("transmission.ids_file is required by qbittorrent backend".to_string()),
+        },
+    }
+}
+
+/// Transmission implementation of operations shared with other backends.
+struct TransmissionClient {
+    url: reqwest::Url,
 }
 
-fn file_filter(msg: Message) -> bool{
+#[async_trait]
+impl TorrentClient for TransmissionClient {
+    fn backend(&self) -> Backend {
+        Backend::Transmission
+    }
+
+    async fn list(&self) -> Result<Vec<TorrentInfo>, String> {
+        let mut client = TransClient::new(self.url.clone());
+        let fields = vec![
+            types::TorrentGetField::Id,
+            types::TorrentGetField::HashString,
+            types::TorrentGetField::Name,
+            types::TorrentGetField::Status,
+            types::TorrentGetField::TotalSize,
+            types::TorrentGetField::LeftUntilDone,
+            types::TorrentGetField::PercentDone,
+            types::TorrentGetField::IsStalled,
+        ];
+        let torrents = client.torrent_get(Some(fields), None).await.map_err(|x| x.to_string())?.arguments.torrents;
+        Ok(torrents.into_iter().filter_map(|torrent| Some(TorrentInfo {
+            id: torrent.id?,
+            hash: torrent.hash_string.unwrap_or_default(),
+            name: torrent.name.unwrap_or("N/A".to_string()),
+            status: format!("{:?}", torrent.status?),
+            percent_done: torrent.percent_done.unwrap_or_default() as f64,
+            stalled: torrent.is_stalled == Some(true),
+        })).collect())
+    }
+
+    async fn add(&self, source: TorrentSource, options: AddOptions) -> Result<Added, String> {
+        let mut client = TransClient::new(self.url.clone());
+        let mut add = types::TorrentAddArgs {
+            download_dir: options.download_dir,
+            paused: options.paused.then_some(true),
+            labels: (!options.labels.is_empty()).then_some(options.labels),
+            ..types::TorrentAddArgs::default()
+        };
+        match source {
+            TorrentSource::File(data) => add.metainfo = Some(base64::engine::general_purpose::STANDARD.encode(data)),
+            TorrentSource::Link(link) => add.filename = Some(link),
+        }
+        let res = client.torrent_add(add).await.map_err(|x| x.to_string())?;
+        if !res.is_ok() {
+            return Err(res.result);
+        }
+        let (torrent, duplicate) = match res.arguments {
+            types::TorrentAddedOrDuplicate::TorrentAdded(torrent) => (torrent, false),
+            types::TorrentAddedOrDuplicate::TorrentDuplicate(torrent) => (torrent, true),
+        };
+        Ok(Added {
+            hash: torrent.hash_string,
+            name: torrent.name,
+            duplicate,
+        })
+    }
+
+    async fn start(&self, torrents: &[TorrentInfo]) -> Result<(), String> {
+        self.action(types::TorrentAction::Start, torrents).await
+    }
+
+    async fn stop(&self, torrents: &[TorrentInfo]) -> Result<(), String> {
+        self.action(types::TorrentAction::Stop, torrents).await
+    }
+
+    async fn remove(&self, torrents: &[TorrentInfo], with_data: bool) -> Result<(), String> {
+        let mut client = TransClient::new(self.url.clone());
+        match client.torrent_remove(torrent_ids(torrents), with_data).await {
+            Ok(res) if res.is_ok() => Ok(()),
+            Ok(res) => Err(res.result),
+            Err(x) => Err(x.to_string()),
+        }
+    }
+}
+
+impl TransmissionClient {
+    async fn action(&self, action: types::TorrentAction, torrents: &[TorrentInfo]) -> Result<(), String> {
+        let mut client = TransClient::new(self.url.clone());
+        match client.torrent_action(action, torrent_ids(torrents)).await {
+            Ok(res) if res.is_ok() => Ok(()),
+            Ok(res) => Err(res.result),
+            Err(x) => Err(x.to_string()),
+        }
+    }
+}
+
+fn file_filter(msg: &Message) -> bool{
     if let MessageKind::Common(data) = &msg.kind {
         if let MediaKind::Document(doc) = &data.media_kind{
             let mime: mime::Mime = "application/x-bittorrent".parse().unwrap();
@@ -53,163 +403,1038 @@ fn file_filter(msg: Message) -> bool{
             }
         }
     }
-    return false;
+    false
+}
+
+/// Magnet link pasted as plain text message.
+fn magnet_filter(msg: &Message) -> bool {
+    msg.text().is_some_and(|text| text.trim_start().starts_with("magnet:?"))
 }
 
-async fn add_file(bot:Bot, msg: Message ,url:String) -> ResponseResult<()>{
-    let mut client = TransClient::new(url.parse().unwrap());
-    let add: types::TorrentAddArgs = types::TorrentAddArgs {
-        filename: Some(
-            get_download_link(&bot, &msg).await
-        ),
-        ..types::TorrentAddArgs::default()
+async fn add_file(bot:Bot, msg: Message, context: Context) -> ResponseResult<()>{
+    let Context { client, owners, categories, uploads, .. } = context;
+    let data = match download_torrent_file(&bot, &msg).await {
+        Ok(data) => data,
+        Err(x) => {
+            bot.send_message(msg.chat.id, audit::failure(format!("Failed with err: {}", x))).await?;
+            return Ok(());
+        }
     };
+    // Caption selects category, files without caption are added to the default directory
+    let Some(options) = caption_options(&categories, msg.caption()) else {
+        return ask_category(&bot, &msg, data, &categories, &uploads).await;
+    };
+    let message = add_torrent(client.as_ref(), TorrentSource::File(data), options, msg.chat.id, &owners).await;
+    bot.send_message(msg.chat.id, message).await?;
+    Ok(())
+}
 
-    match client.torrent_add(add).await{
-        Ok(res) =>{ if res.is_ok(){
-            bot.send_message(msg.chat.id, "Added sucessfully").await?;
-        } else {
-            bot.send_message(msg.chat.id, "Failed").await?;
-        }},
-        Err(x) => {bot.send_message(msg.chat.id, format!("Failed with err: {}", x)).await?;}
+fn category_options(category: &Category) -> AddOptions {
+    AddOptions {
+        download_dir: category.download_dir.clone(),
+        labels: category.labels.clone(),
+        ..AddOptions::default()
+    }
+}
 
+/// Options of dropped file selected by its caption. `None` means the caption is unknown and category must be chosen.
+fn caption_options(categories: &HashMap<String, Category>, caption: Option<&str>) -> Option<AddOptions> {
+    let caption = caption.map(|caption| caption.trim().to_lowercase()).filter(|caption| !caption.is_empty());
+    match caption {
+        Some(caption) if !categories.is_empty() => categories.get(&caption).map(category_options),
+        _ => Some(AddOptions::default()),
     }
+}
+
+/// Category names in order of the buttons, buttons refer to categories by index as callback data is limited to 64 bytes.
+fn category_names(categories: &HashMap<String, Category>) -> Vec<&String> {
+    let mut names: Vec<&String> = categories.keys().collect();
+    names.sort();
+    names
+}
 
+/// Options of category chosen with button, empty choice is the default directory.
+fn chosen_options(categories: &HashMap<String, Category>, choice: &str) -> Option<AddOptions> {
+    if choice.is_empty() {
+        return Some(AddOptions::default());
+    }
+    let index = choice.parse::<usize>().ok()?;
+    category_names(categories).get(index).map(|name| category_options(&categories[*name]))
+}
+
+/// Buttons choosing category of pending upload `id`.
+fn category_keyboard(categories: &HashMap<String, Category>, id: u64) -> InlineKeyboardMarkup {
+    let names = category_names(categories);
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = names.iter().enumerate()
+        .map(|(index, name)| InlineKeyboardButton::callback(name.as_str(), format!("tr:category:{}:{}", id, index)))
+        .collect::<Vec<InlineKeyboardButton>>()
+        .chunks(3)
+        .map(|row| row.to_vec())
+        .collect();
+    buttons.push(vec![
+        InlineKeyboardButton::callback("Default", format!("tr:category:{}:", id)),
+        InlineKeyboardButton::callback("Cancel", format!("tr:category:{}:cancel", id)),
+    ]);
+    InlineKeyboardMarkup::new(buttons)
+}
+
+/// Ask to choose category of dropped file with unknown caption.
+async fn ask_category(bot: &Bot, msg: &Message, data: Vec<u8>, categories: &Categories, uploads: &Uploads) -> ResponseResult<()> {
+    let id = uploads.next_id.fetch_add(1, Ordering::Relaxed);
+    bot.send_message(msg.chat.id, format!("Unknown category {}, please choose one", msg.caption().unwrap_or_default().trim()))
+        .reply_markup(category_keyboard(categories, id))
+        .await?;
+    let mut pending = uploads.pending.lock().unwrap();
+    pending.retain(|_, upload| upload.created.elapsed() <= Duration::from_secs(UPLOAD_TIMEOUT));
+    pending.insert(id, PendingUpload {
+        user: msg.from().map(|user| user.id),
+        data,
+        created: Instant::now(),
+    });
     Ok(())
 }
 
-async fn get_download_link(bot: &Bot,msg: &Message) -> String{
-    let mut link = String::new();
-    if let MessageKind::Common(data) = &msg.kind {
-        if let MediaKind::Document(doc) = &data.media_kind{
-            let mime: mime::Mime = "application/x-bittorrent".parse().unwrap();
-            if Some(mime) == doc.document.mime_type{
-                let data = bot.get_file(&doc.document.file.id).await.unwrap();
-                link = format!("https://api.telegram.org/file/bot{}/{}", bot.token(), data.path);
+async fn category_callback(bot: Bot, query: CallbackQuery, context: Context) -> ResponseResult<()> {
+    let Context { client, owners, categories, uploads, .. } = context;
+    let (Some(data), Some(msg)) = (&query.data, &query.message) else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    let args: Vec<&str> = data.splitn(4, ':').skip(2).collect();
+    let (Some(Ok(id)), Some(choice)) = (args.first().map(|id| id.parse::<u64>()), args.get(1)) else {
+        bot.answer_callback_query(query.id).text("Invalid button").await?;
+        return Ok(());
+    };
+    let options = match *choice {
+        "cancel" => None,
+        choice => match chosen_options(&categories, choice) {
+            Some(options) => Some(options),
+            None => {
+                bot.answer_callback_query(query.id).text("Invalid button").await?;
+                return Ok(());
+            }
+        },
+    };
+    let pending = {
+        let mut pending = uploads.pending.lock().unwrap();
+        match pending.get(&id) {
+            Some(upload) if upload.user.is_some_and(|user| user != query.from.id) => None,
+            Some(_) => pending.remove(&id),
+            None => None,
+        }
+    };
+    let Some(upload) = pending else {
+        bot.answer_callback_query(query.id).text("File expired or was sent by another user").await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(query.id).await?;
+
+    let text = if upload.created.elapsed() > Duration::from_secs(UPLOAD_TIMEOUT) {
+        "File expired, please send it again".to_string()
+    } else if let Some(options) = options {
+        add_torrent(client.as_ref(), TorrentSource::File(upload.data), options, msg.chat.id, &owners).await
+    } else {
+        "Cancelled".to_string()
+    };
+    bot.edit_message_text(msg.chat.id, msg.id, text).await?;
+    Ok(())
+}
+
+/// Download dropped .torrent file. The file is downloaded by the bot itself so the bot token
+/// is never passed to the torrent client.
+async fn download_torrent_file(bot: &Bot, msg: &Message) -> Result<Vec<u8>, String> {
+    let doc = msg.document().ok_or("Message has no document")?;
+    let file = bot.get_file(&doc.file.id).await.map_err(|x| x.to_string())?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data).await.map_err(|x| x.to_string())?;
+    Ok(data)
+}
+
+async fn add_magnet(bot: Bot, msg: Message, context: Context) -> ResponseResult<()> {
+    let link = msg.text().unwrap_or_default().trim().to_string();
+    let message = add_torrent(context.client.as_ref(), TorrentSource::Link(link), AddOptions::default(), msg.chat.id, &context.owners).await;
+    bot.send_message(msg.chat.id, message).await?;
+    Ok(())
+}
+
+/// Add torrent and remember the chat it was added from for notifications.
+async fn add_torrent(client: &dyn TorrentClient, source: TorrentSource, options: AddOptions, chat: ChatId, owners: &Owners) -> String {
+    match client.add(source, options).await {
+        Ok(added) => {
+            if let Some(hash) = added.hash {
+                owners.lock().unwrap().insert(hash, chat);
             }
+            let message = if added.duplicate { "Already added" } else { "Added sucessfully" };
+            match added.name {
+                Some(name) => format!("{}: {}", message, name),
+                None => message.to_string(),
+            }
+        },
+        Err(x) => audit::failure(format!("Failed with err: {}", x))
+    }
+}
+
+/// Arguments of `add`: magnet link or url followed by optional `paused` flag and download directory.
+fn parse_add_args(com: &[&str]) -> Result<(TorrentSource, AddOptions), String> {
+    let com: Vec<&str> = com.iter().copied().filter(|arg| !arg.is_empty()).collect();
+    let Some(link) = com.first() else {
+        return Err("Please provide magnet link or url".to_string());
+    };
+    if !(link.starts_with("magnet:?") || link.starts_with("http://") || link.starts_with("https://")) {
+        return Err(format!("{} is not a magnet link or url", link));
+    }
+    let mut options = AddOptions::default();
+    for arg in &com[1..] {
+        if *arg == "paused" {
+            options.paused = true;
+        } else if options.download_dir.is_none() {
+            options.download_dir = Some(arg.to_string());
+        } else {
+            return Err(format!("Unexpected argument {}", arg));
         }
     }
-    return link;
+    Ok((TorrentSource::Link(link.to_string()), options))
 }
 
 async fn command_handler(
     bot: Bot,
-    msg: Message,
     cmd: Command,
-    url: String
+    upd: Update,
+    me: Me,
+    resolved: Resolved,
+    context: Context,
+    confirmation: Arc<Confirmation>
 ) -> ResponseResult<()>{
-        let mut client = TransClient::new(url.parse().unwrap());
+        let Context { client: backend, owners, rpc, .. } = context;
+        let UpdateKind::Message(msg) = upd.kind.clone() else {
+            return Ok(());
+        };
         match cmd {
             Command::Transmission(command) => {
-                let com: Vec<&str> = command.trim().split(" ").collect();
-                if com[0] == "" || com[0] == "list"{
-                    bot.send_message(msg.chat.id, list_torrent(&mut client).await).await?;
-                } else if com[0] == "stop"{
-                    let is_value = com.len()>=2;
-                    if is_value{
-                        bot.send_message(msg.chat.id, pause_torrent(&mut client, com[1].parse().unwrap()).await).await?;
-                    } else {
-                        bot.send_message(msg.chat.id, "Please provide torrent id").await?;
-                    }
-                } else if com[0] == "start"{
-                    let is_value = com.len()>=2;
-                    if is_value{
-                        bot.send_message(msg.chat.id, start_torrent(&mut client, com[1].parse().unwrap()).await).await?;
+                let com = arguments(&command);
+                if com.is_empty() || com[0] == "list"{
+                    bot.send_message(msg.chat.id, list_torrent(backend.as_ref()).await).await?;
+                } else if com[0] == "stop" || com[0] == "start"{
+                    if com.len() >= 2 {
+                        let message = match select_torrents(backend.as_ref(), &resolved_selector(&com, &resolved)).await {
+                            Ok(torrents) if com[0] == "stop" => pause_torrent(backend.as_ref(), &torrents).await,
+                            Ok(torrents) => start_torrent(backend.as_ref(), &torrents).await,
+                            Err(x) => audit::failure(x),
+                        };
+                        bot.send_message(msg.chat.id, message).await?;
                     } else {
-                        bot.send_message(msg.chat.id, "Please provide torrent id").await?;
+                        bot.send_message(msg.chat.id, "Please provide torrent id or selector").await?;
                     }
                 } else if com[0] == "remove"{
                     let is_value = com.len()>=3;
                     if is_value{
-                        let with_data = match com[2].to_lowercase().as_str(){
-                            "yes" => true,
-                            "no" => false,
-                            "n" => false,
-                            "y" => true,
-                            _ => false
-                        };
-                        bot.send_message(msg.chat.id, remove_torrent(&mut client, com[1].parse().unwrap(), with_data).await).await?;
+                        let with_data = matches!(removal_flag(com[com.len() - 1]), "yes" | "y");
+                        match select_torrents(backend.as_ref(), &resolved_selector(&com, &resolved)).await {
+                            Ok(torrents) if previews_removal(&com) => preview_removal(&bot, &upd, &me, backend.clone(), torrents, with_data, &confirmation).await?,
+                            Ok(torrents) => {
+                                bot.send_message(msg.chat.id, remove_torrent(backend.as_ref(), &torrents, with_data).await).await?;
+                            },
+                            Err(x) => {bot.send_message(msg.chat.id, audit::failure(x)).await?;},
+                        }
                     } else {
-                        bot.send_message(msg.chat.id, "Please provide 2 parameters (torrent id, with_data (yes,no) )").await?;
+                        bot.send_message(msg.chat.id, "Please provide 2 parameters (torrent id or selector, with_data (yes,no) )").await?;
                     }
+                } else if com[0] == "add"{
+                    match parse_add_args(&com[1..]) {
+                        Ok((source, options)) => bot.send_message(msg.chat.id, add_torrent(backend.as_ref(), source, options, msg.chat.id, &owners).await).await?,
+                        Err(x) => bot.send_message(msg.chat.id, x).await?,
+                    };
                 } else if com[0] == "help"{
                     bot.send_message(msg.chat.id, get_command_handler_help_text()).await?;
+                } else if let Some(url) = &rpc {
+                    transmission_command(&bot, &msg, &com, &resolved, url, backend.as_ref()).await?;
+                } else {
+                    // Other backends support only the commands of TorrentClient
+                    bot.send_message(msg.chat.id, format!("{} is not supported by {:?} backend", com[0], backend.backend())).await?;
                 }
             }
         }
         Ok(())
     }
 
-async fn list_torrent(client: &mut TransClient) -> String{
+/// Command arguments split on any whitespace, the same way as `parse_command` splits them for permissions.
+fn arguments(command: &str) -> Vec<&str> {
+    command.split_whitespace().collect()
+}
+
+/// Commands available only with transmission backend, they use transmission rpc directly.
+async fn transmission_command(bot: &Bot, msg: &Message, com: &[&str], resolved: &Resolved, url: &reqwest::Url, backend: &dyn TorrentClient) -> ResponseResult<()> {
+    let mut client = TransClient::new(url.clone());
+    if com[0] == "verify" || com[0] == "reannounce" || com[0] == "start-now"{
+        if com.len() >= 2 {
+            let (action, done) = match com[0] {
+                "verify" => (types::TorrentAction::Verify, "Verification started"),
+                "reannounce" => (types::TorrentAction::Reannounce, "Reannounced"),
+                _ => (types::TorrentAction::StartNow, "Started bypassing queue"),
+            };
+            let message = match select_torrents(backend, &resolved_selector(com, resolved)).await {
+                Ok(torrents) => torrent_action(&mut client, action, &torrents, done).await,
+                Err(x) => audit::failure(x),
+            };
+            bot.send_message(msg.chat.id, message).await?;
+        } else {
+            bot.send_message(msg.chat.id, "Please provide torrent id or selector").await?;
+        }
+    } else if com[0] == "queue"{
+        let direction = com.get(1).filter(|direction| ["top", "up", "down", "bottom"].contains(*direction));
+        match direction {
+            Some(direction) if com.len() >= 3 => {
+                let message = match select_torrents(backend, &resolved_selector(com, resolved)).await {
+                    Ok(torrents) => queue_move(url.as_str(), direction, &torrents).await,
+                    Err(x) => audit::failure(x),
+                };
+                bot.send_message(msg.chat.id, message).await?;
+            },
+            _ => {bot.send_message(msg.chat.id, "Please provide direction (top, up, down, bottom) and torrent id or selector").await?;},
+        }
+    } else if com[0] == "move"{
+        if com.len() >= 3 {
+            let message = match select_torrents(backend, &resolved_selector(com, resolved)).await {
+                Ok(torrents) => move_torrent(&mut client, &torrents, com[2..].join(" ")).await,
+                Err(x) => audit::failure(x),
+            };
+            bot.send_message(msg.chat.id, message).await?;
+        } else {
+            bot.send_message(msg.chat.id, "Please provide 2 parameters (torrent id or selector, new location)").await?;
+        }
+    } else if com[0] == "info"{
+        match com.get(1).map(|id| id.parse::<i64>()) {
+            Some(Ok(id)) => {
+                for part in split_message(&torrent_info(&mut client, url.as_str(), id).await) {
+                    bot.send_message(msg.chat.id, part).await?;
+                }
+            },
+            Some(Err(_)) => {bot.send_message(msg.chat.id, format!("Invalid torrent id {}", com[1])).await?;},
+            None => {bot.send_message(msg.chat.id, "Please provide torrent id").await?;},
+        }
+    } else if com[0] == "files"{
+        match com.get(1).map(|id| id.parse::<i64>()) {
+            Some(Ok(id)) => match files_view(&mut client, id, 0).await {
+                Ok((text, keyboard)) => {bot.send_message(msg.chat.id, text).reply_markup(keyboard).await?;},
+                Err(x) => {bot.send_message(msg.chat.id, audit::failure(x)).await?;},
+            },
+            Some(Err(_)) => {bot.send_message(msg.chat.id, format!("Invalid torrent id {}", com[1])).await?;},
+            None => {bot.send_message(msg.chat.id, "Please provide torrent id").await?;},
+        }
+    } else if com[0] == "speed"{
+        bot.send_message(msg.chat.id, speed(&mut client, url.as_str()).await).await?;
+    } else if com[0] == "limit"{
+        match parse_limit_args(&com[1..]) {
+            Ok(args) => bot.send_message(msg.chat.id, session_set(&mut client, args).await).await?,
+            Err(x) => bot.send_message(msg.chat.id, x).await?,
+        };
+    } else if com[0] == "turtle"{
+        let enabled = match com.get(1) {
+            Some(&"on") => Some(true),
+            Some(&"off") => Some(false),
+            _ => None,
+        };
+        if let Some(enabled) = enabled {
+            let args = types::SessionSetArgs {
+                alt_speed_enabled: Some(enabled),
+                ..types::SessionSetArgs::default()
+            };
+            bot.send_message(msg.chat.id, session_set(&mut client, args).await).await?;
+        } else {
+            bot.send_message(msg.chat.id, "Please provide on or off").await?;
+        }
+    } else if com[0] == "stats"{
+        bot.send_message(msg.chat.id, session_stats(&mut client).await).await?;
+    }
+    Ok(())
+}
+
+async fn list_torrent(client: &dyn TorrentClient) -> String{
     let mut message = String::new();
 
-    match client.torrent_get(None, None).await{
-        Ok(res) => {
-            for torrent in res.arguments.torrents{
-                message += format!("{:?}: {:?} {:?} {:.0}%\n",
-                    torrent.id.unwrap(),
-                    torrent.name.unwrap().substring(0,10).to_owned() + "...",
-                    torrent.status.unwrap(),
-                    ((torrent.total_size.unwrap() as f64 - torrent.left_until_done.unwrap() as f64)/torrent.total_size.unwrap() as f64)*100.0
+    match client.list().await{
+        Ok(torrents) => {
+            for torrent in torrents{
+                message += format!("{:?}: {:?} {} {:.0}%\n",
+                    torrent.id,
+                    torrent.name.substring(0,10).to_owned() + "...",
+                    torrent.status,
+                    torrent.percent_done * 100.0
                 ).as_str();
             }
         },
-        Err(x) => println!("{}", x)
-    } 
+        Err(x) => return audit::failure(format!("Failed with err: {}", x))
+    }
+
+    message
+}
+
+async fn torrent_info(client: &mut TransClient, url: &str, id: i64) -> String {
+    let fields = vec![
+        types::TorrentGetField::Id,
+        types::TorrentGetField::Name,
+        types::TorrentGetField::Status,
+        types::TorrentGetField::PercentDone,
+        types::TorrentGetField::SizeWhenDone,
+        types::TorrentGetField::RateDownload,
+        types::TorrentGetField::RateUpload,
+        types::TorrentGetField::Eta,
+        types::TorrentGetField::UploadRatio,
+        types::TorrentGetField::PeersConnected,
+        types::TorrentGetField::Trackers,
+        types::TorrentGetField::ErrorString,
+        types::TorrentGetField::DownloadDir,
+        types::TorrentGetField::Files,
+    ];
+    let torrent = match client.torrent_get(Some(fields), Some(vec![types::Id::Id(id)])).await {
+        Ok(res) => match res.arguments.torrents.into_iter().next() {
+            Some(torrent) => torrent,
+            None => return format!("Torrent {} not found", id),
+        },
+        Err(x) => return audit::failure(format!("Failed with err: {}", x)),
+    };
+
+    let eta = match torrent.eta {
+        Some(eta) if eta >= 0 => format_duration(eta as u64),
+        _ => "N/A".to_string(),
+    };
+    let mut message = format!("{}: {}\nStatus: {:?} {:.1}% of {}\nDownload: {}/s, upload: {}/s\nETA: {}\nRatio: {:.2}\nPeers: {}\nDirectory: {}\n",
+        id,
+        torrent.name.unwrap_or("N/A".to_string()),
+        torrent.status.unwrap_or(types::TorrentStatus::Stopped),
+        torrent.percent_done.unwrap_or_default() * 100.0,
+        format_size(torrent.size_when_done.unwrap_or_default()),
+        format_size(torrent.rate_download.unwrap_or_default()),
+        format_size(torrent.rate_upload.unwrap_or_default()),
+        eta,
+        torrent.upload_ratio.unwrap_or_default(),
+        torrent.peers_connected.unwrap_or_default(),
+        torrent.download_dir.unwrap_or("N/A".to_string())
+    );
+    if let Some(error) = torrent.error_string.filter(|error| !error.is_empty()) {
+        message += format!("Error: {}\n", error).as_str();
+    }
+
+    message += "\nTrackers:\n";
+    match tracker_stats(url, id).await {
+        Ok(trackers) => {
+            for tracker in trackers {
+                let status = if !tracker.has_announced {
+                    "not announced yet".to_string()
+                } else if tracker.last_announce_succeeded {
+                    "OK".to_string()
+                } else {
+                    format!("failed: {}", tracker.last_announce_result)
+                };
+                message += format!("{}: {} (seeders: {}, leechers: {})\n", tracker.host, status, tracker.seeder_count, tracker.leecher_count).as_str();
+            }
+        },
+        Err(x) => {
+            // Still show tracker urls when status couldn't be fetched
+            for tracker in torrent.trackers.unwrap_or_default() {
+                message += format!("{}\n", tracker.announce).as_str();
+            }
+            message += format!("Failed to get tracker status: {}\n", x).as_str();
+        }
+    }
+
+    message += "\nFiles:\n";
+    for file in torrent.files.unwrap_or_default() {
+        let percent = if file.length > 0 { file.bytes_completed as f64 / file.length as f64 * 100.0 } else { 100.0 };
+        message += format!("{:.0}% {} ({})\n", percent, file.name, format_size(file.length)).as_str();
+    }
+    message
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TrackerStat {
+    host: String,
+    has_announced: bool,
+    last_announce_succeeded: bool,
+    last_announce_result: String,
+    seeder_count: i64,
+    leecher_count: i64,
+}
+
+/// Announce status of torrent trackers. `trackerStats` field isn't supported by transmission-rpc.
+async fn tracker_stats(url: &str, id: i64) -> Result<Vec<TrackerStat>, String> {
+    let arguments = rpc_call(url, "torrent-get", serde_json::json!({"ids": [id], "fields": ["trackerStats"]})).await?;
+    serde_json::from_value(arguments["torrents"][0]["trackerStats"].clone()).map_err(|x| x.to_string())
+}
+
+/// Call RPC method directly, for requests and fields that transmission-rpc doesn't support.
+/// Returns `arguments` of the response.
+async fn rpc_call(url: &str, method: &str, arguments: serde_json::Value) -> Result<serde_json::Value, String> {
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({
+        "method": method,
+        "arguments": arguments,
+    });
+    let mut session_id = String::new();
+    // Transmission answers 409 with session id to the first request
+    for _ in 0..2 {
+        let rsp = client.post(url)
+            .header("X-Transmission-Session-Id", &session_id)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|x| x.to_string())?;
+        if rsp.status() == reqwest::StatusCode::CONFLICT {
+            session_id = rsp.headers()
+                .get("X-Transmission-Session-Id")
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            continue;
+        }
+        let mut value: serde_json::Value = rsp.error_for_status()
+            .map_err(|x| x.to_string())?
+            .json()
+            .await
+            .map_err(|x| x.to_string())?;
+        if value["result"] != "success" {
+            return Err(value["result"].as_str().unwrap_or("Unknown error").to_string());
+        }
+        return Ok(value["arguments"].take());
+    }
+    Err("Transmission didn't accept session id".to_string())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct SpeedLimits {
+    speed_limit_down: i64,
+    speed_limit_down_enabled: bool,
+    speed_limit_up: i64,
+    speed_limit_up_enabled: bool,
+    alt_speed_enabled: bool,
+    alt_speed_down: i64,
+    alt_speed_up: i64,
+}
 
+/// Current rates with speed limits and turtle mode state.
+async fn speed(client: &mut TransClient, url: &str) -> String {
+    let stats = match client.session_stats().await {
+        Ok(res) => res.arguments,
+        Err(x) => return audit::failure(format!("Failed with err: {}", x)),
+    };
+    let fields = serde_json::json!({"fields": [
+        "speed-limit-down", "speed-limit-down-enabled", "speed-limit-up", "speed-limit-up-enabled",
+        "alt-speed-enabled", "alt-speed-down", "alt-speed-up",
+    ]});
+    let limits: SpeedLimits = match rpc_call(url, "session-get", fields).await
+        .and_then(|arguments| serde_json::from_value(arguments).map_err(|x| x.to_string())) {
+        Ok(limits) => limits,
+        Err(x) => return audit::failure(format!("Failed with err: {}", x)),
+    };
+    let limit = |enabled: bool, value: i64| if enabled { format!("{} KB/s", value) } else { "off".to_string() };
+    format!("Download: {}/s (limit: {})\nUpload: {}/s (limit: {})\nTurtle mode: {} (down: {} KB/s, up: {} KB/s)\nTorrents: {} active, {} paused of {}",
+        format_size(stats.download_speed),
+        limit(limits.speed_limit_down_enabled, limits.speed_limit_down),
+        format_size(stats.upload_speed),
+        limit(limits.speed_limit_up_enabled, limits.speed_limit_up),
+        if limits.alt_speed_enabled { "on" } else { "off" },
+        limits.alt_speed_down,
+        limits.alt_speed_up,
+        stats.active_torrent_count,
+        stats.paused_torrent_count,
+        stats.torrent_count
+    )
+}
 
-    return message
+/// Arguments of `limit`: direction `down` or `up` and limit in KB/s or `off`.
+fn parse_limit_args(com: &[&str]) -> Result<types::SessionSetArgs, String> {
+    let (Some(direction), Some(value)) = (com.first(), com.get(1)) else {
+        return Err("Please provide direction (down, up) and limit in KB/s or off".to_string());
+    };
+    let limit = match *value {
+        "off" => None,
+        value => Some(value.parse::<i32>().ok().filter(|limit| *limit >= 0).ok_or(format!("Invalid limit {}", value))?),
+    };
+    let mut args = types::SessionSetArgs::default();
+    match *direction {
+        "down" => {
+            args.speed_limit_down_enabled = Some(limit.is_some());
+            args.speed_limit_down = limit;
+        },
+        "up" => {
+            args.speed_limit_up_enabled = Some(limit.is_some());
+            args.speed_limit_up = limit;
+        },
+        _ => return Err(format!("Unknown direction {}, must be down or up", direction)),
+    }
+    Ok(args)
 }
 
-async fn pause_torrent(client: &mut TransClient, id: i64) -> String{
-    match client
-    .torrent_action(types::TorrentAction::Stop, vec![types::Id::Id(id)])
-    .await{
+async fn session_set(client: &mut TransClient, args: types::SessionSetArgs) -> String {
+    match client.session_set(args).await {
         Ok(res) => {
-            if res.is_ok(){
-                return "Paused successfully".to_string();
+            if res.is_ok() {
+                "Updated successfully".to_string()
             } else {
-                return "Pause failed".to_string();
+                audit::failure(format!("Update failed: {}", res.result))
             }
         },
-        Err(x) => format!("Pause failed with err: {}", x)
+        Err(x) => audit::failure(format!("Update failed with err: {}", x))
     }
 }
 
-async fn start_torrent(client: &mut TransClient, id: i64) -> String{
-    match client
-    .torrent_action(types::TorrentAction::Start, vec![types::Id::Id(id)])
-    .await{
+async fn session_stats(client: &mut TransClient) -> String {
+    match client.session_stats().await {
         Ok(res) => {
-            if res.is_ok(){
-                return "Started successfully".to_string();
+            let stats = res.arguments;
+            // Stats type isn't exported by transmission-rpc, so the values are passed one by one
+            let totals = |name: &str, downloaded: i64, uploaded: i64, files_added: i32, seconds_active: i64| format!("{}:\n  Downloaded: {}\n  Uploaded: {}\n  Files added: {}\n  Active: {}\n",
+                name,
+                format_size(downloaded),
+                format_size(uploaded),
+                files_added,
+                format_duration(seconds_active.max(0) as u64)
+            );
+            let current = &stats.current_stats;
+            let cumulative = &stats.cumulative_stats;
+            let mut message = totals("Current session", current.downloaded_bytes, current.uploaded_bytes, current.files_added, current.seconds_active);
+            message += totals("Total", cumulative.downloaded_bytes, cumulative.uploaded_bytes, cumulative.files_added, cumulative.seconds_active).as_str();
+            if let Some(count) = stats.cumulative_stats.session_count {
+                message += format!("  Sessions: {}\n", count).as_str();
+            }
+            message
+        },
+        Err(x) => audit::failure(format!("Failed with err: {}", x))
+    }
+}
+
+const FILES_PER_PAGE: usize = 10;
+
+/// Page of torrent files with buttons toggling wanted state and setting priority.
+/// Callback data is `tr:files:<id>:page:<page>` or `tr:files:<id>:<file index>:<action>`.
+async fn files_view(client: &mut TransClient, id: i64, page: usize) -> Result<(String, InlineKeyboardMarkup), String> {
+    let fields = vec![
+        types::TorrentGetField::Name,
+        types::TorrentGetField::Files,
+        types::TorrentGetField::FileStats,
+    ];
+    let torrent = match client.torrent_get(Some(fields), Some(vec![types::Id::Id(id)])).await {
+        Ok(res) => res.arguments.torrents.into_iter().next().ok_or(format!("Torrent {} not found", id))?,
+        Err(x) => return Err(format!("Failed with err: {}", x)),
+    };
+    files_page(id, torrent, page)
+}
+
+/// Text and keyboard of `files_view` page for torrent with `name`, `files` and `file_stats` fields.
+fn files_page(id: i64, torrent: types::Torrent, page: usize) -> Result<(String, InlineKeyboardMarkup), String> {
+    let files = torrent.files.unwrap_or_default();
+    let stats = torrent.file_stats.unwrap_or_default();
+    if files.is_empty() {
+        return Err("Torrent has no files yet".to_string());
+    }
+    let pages = files.len().div_ceil(FILES_PER_PAGE);
+    let page = page.min(pages - 1);
+
+    let mut text = format!("Files of {} (page {}/{}):\n", torrent.name.unwrap_or("N/A".to_string()), page + 1, pages);
+    let mut buttons = Vec::new();
+    for (index, (file, stat)) in files.iter().zip(stats.iter()).enumerate().skip(page * FILES_PER_PAGE).take(FILES_PER_PAGE) {
+        let percent = if file.length > 0 { file.bytes_completed as f64 / file.length as f64 * 100.0 } else { 100.0 };
+        text += format!("{}. {} {:.0}% {} ({})\n",
+            index + 1,
+            if stat.wanted { "[x]" } else { "[ ]" },
+            percent,
+            file.name,
+            format_size(file.length)
+        ).as_str();
+        let priority = |label: &str, value: i8, action: &str| {
+            let label = if stat.priority == value { format!("• {}", label) } else { label.to_string() };
+            InlineKeyboardButton::callback(label, format!("tr:files:{}:{}:{}", id, index, action))
+        };
+        buttons.push(vec![
+            InlineKeyboardButton::callback(
+                format!("{}: {}", index + 1, if stat.wanted { "skip" } else { "download" }),
+                format!("tr:files:{}:{}:{}", id, index, if stat.wanted { "unwanted" } else { "wanted" })
+            ),
+            priority("High", 1, "high"),
+            priority("Normal", 0, "normal"),
+            priority("Low", -1, "low"),
+        ]);
+    }
+    let mut navigation = Vec::new();
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback("« Prev", format!("tr:files:{}:page:{}", id, page - 1)));
+    }
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback("Next »", format!("tr:files:{}:page:{}", id, page + 1)));
+    }
+    if !navigation.is_empty() {
+        buttons.push(navigation);
+    }
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
+
+async fn files_callback(bot: Bot, query: CallbackQuery, url: reqwest::Url) -> ResponseResult<()> {
+    let (Some(data), Some(msg)) = (&query.data, &query.message) else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+    let mut client = TransClient::new(url);
+    let args: Vec<&str> = data.split(':').skip(2).collect();
+    let result = match args.as_slice() {
+        [id, "page", page] => match (id.parse::<i64>(), page.parse::<usize>()) {
+            (Ok(id), Ok(page)) => files_view(&mut client, id, page).await,
+            _ => Err("Invalid button".to_string()),
+        },
+        [id, index, action] => match (id.parse::<i64>(), index.parse::<i32>()) {
+            (Ok(id), Ok(index)) => match set_file(&mut client, id, index, action).await {
+                Ok(()) => files_view(&mut client, id, index as usize / FILES_PER_PAGE).await,
+                Err(x) => Err(x),
+            },
+            _ => Err("Invalid button".to_string()),
+        },
+        _ => Err("Invalid button".to_string()),
+    };
+    match result {
+        Ok((text, keyboard)) => {
+            bot.answer_callback_query(query.id).await?;
+            bot.edit_message_text(msg.chat.id, msg.id, text).reply_markup(keyboard).await?;
+        },
+        Err(x) => {
+            bot.answer_callback_query(query.id).text(audit::failure(x)).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn set_file(client: &mut TransClient, id: i64, index: i32, action: &str) -> Result<(), String> {
+    let mut args = types::TorrentSetArgs::default();
+    match action {
+        "wanted" => args.files_wanted = Some(vec![index]),
+        "unwanted" => args.files_unwanted = Some(vec![index]),
+        "high" => args.priority_high = Some(vec![index]),
+        "normal" => args.priority_normal = Some(vec![index]),
+        "low" => args.priority_low = Some(vec![index]),
+        _ => return Err(format!("Unknown action {}", action)),
+    }
+    match client.torrent_set(args, Some(vec![types::Id::Id(id)])).await {
+        Ok(res) if res.is_ok() => Ok(()),
+        Ok(res) => Err(format!("Failed: {}", res.result)),
+        Err(x) => Err(format!("Failed with err: {}", x)),
+    }
+}
+
+/// Torrents matched by selector: `all`, `completed`, `stalled`, ids and ranges like `1,3-7` or part of the name.
+async fn select_torrents(client: &dyn TorrentClient, selector: &str) -> Result<Vec<TorrentInfo>, String> {
+    // Invalid ids are reported without asking the client
+    parse_ids(selector)?;
+    let torrents = client.list().await.map_err(|x| format!("Failed with err: {}", x))?;
+    filter_torrents(torrents, selector)
+}
+
+/// Torrents matched by selector, see `select_torrents`. Selector made of digits is always a list of ids.
+fn filter_torrents(torrents: Vec<TorrentInfo>, selector: &str) -> Result<Vec<TorrentInfo>, String> {
+    let ranges = parse_ids(selector)?;
+    let name = selector.to_lowercase();
+    let matched: Vec<TorrentInfo> = torrents.into_iter()
+        .filter(|torrent| match selector {
+            "all" => true,
+            "completed" => torrent.percent_done >= 1.0,
+            "stalled" => torrent.stalled,
+            _ => match &ranges {
+                Some(ranges) => ranges.iter().any(|range| range.contains(&torrent.id)),
+                None => torrent.name.to_lowercase().contains(&name),
+            },
+        })
+        .collect();
+    if matched.is_empty() {
+        Err(format!("No torrents match {}", selector))
+    } else {
+        Ok(matched)
+    }
+}
+
+/// Selector of the command with torrents resolved for the permission check, see `Plugin::resolve`,
+/// so the command acts on the torrents that were allowed even if the list has changed since.
+fn resolved_selector(com: &[&str], resolved: &Resolved) -> String {
+    let Some(targets) = selector_segments(com) else {
+        return String::new();
+    };
+    // Resolved permission has a single id in place of the selector
+    let length = com.len() - targets.len() + 2;
+    let ids: Option<Vec<&str>> = resolved.0.iter().flatten()
+        .map(|permission| {
+            let segments: Vec<&str> = permission.split('.').collect();
+            (segments.len() == length).then(|| segments[targets.start + 1]).filter(|id| id.parse::<i64>().is_ok())
+        })
+        .collect();
+    match ids {
+        Some(ids) if !ids.is_empty() => ids.join(","),
+        _ => com[targets].join(" "),
+    }
+}
+
+/// Position of the torrent selector in command arguments, the same as `command_handler` uses.
+fn selector_segments(com: &[&str]) -> Option<Range<usize>> {
+    match com.first().copied()? {
+        "stop" | "start" | "verify" | "reannounce" | "start-now" if com.len() >= 2 => Some(1..com.len()),
+        "remove" if com.len() >= 3 => Some(1..com.len() - 1),
+        "queue" if com.len() >= 3 => Some(2..com.len()),
+        "move" if com.len() >= 3 => Some(1..2),
+        "info" | "files" if com.len() >= 2 => Some(1..2),
+        _ => None,
+    }
+}
+
+/// `with_data` argument of `/transmission remove` in lowercase, anything but yes means no.
+fn removal_flag(flag: &str) -> &'static str {
+    match flag.to_lowercase().as_str() {
+        "yes" => "yes",
+        "y" => "y",
+        "n" => "n",
+        _ => "no",
+    }
+}
+
+/// Single torrent selected by id is removed right away, other selectors are previewed before removal.
+fn previews_removal(com: &[&str]) -> bool {
+    com.first() == Some(&"remove") && com.len() >= 3 && com[1..com.len() - 1].join(" ").parse::<i64>().is_err()
+}
+
+/// Ids and ranges like `1,3-7`. Returns `None` when selector isn't a list of ids.
+fn parse_ids(selector: &str) -> Result<Option<Vec<RangeInclusive<i64>>>, String> {
+    if selector.is_empty() || !selector.chars().all(|char| char.is_ascii_digit() || char == ',' || char == '-') {
+        return Ok(None);
+    }
+    let parse = |id: &str| id.parse::<i64>().map_err(|_| format!("Invalid torrent id {}", id));
+    let mut ranges = Vec::new();
+    for part in selector.split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let range = from.parse::<i64>().ok().zip(to.parse::<i64>().ok()).filter(|(from, to)| from <= to);
+                let Some((from, to)) = range else {
+                    return Err(format!("Invalid range {}", part));
+                };
+                ranges.push(from..=to);
+            },
+            None => {
+                let id = parse(part)?;
+                ranges.push(id..=id);
+            }
+        }
+    }
+    if ranges.is_empty() {
+        return Err(format!("Invalid torrent ids {}", selector));
+    }
+    Ok(Some(ranges))
+}
+
+fn torrent_ids(torrents: &[TorrentInfo]) -> Vec<types::Id> {
+    torrents.iter().map(|torrent| types::Id::Id(torrent.id)).collect()
+}
+
+fn torrent_list(torrents: &[TorrentInfo]) -> String {
+    torrents.iter().map(|torrent| format!("{}: {}\n", torrent.id, torrent.name)).collect()
+}
+
+async fn pause_torrent(client: &dyn TorrentClient, torrents: &[TorrentInfo]) -> String{
+    match client.stop(torrents).await{
+        Ok(()) => format!("Paused successfully:\n{}", torrent_list(torrents)),
+        Err(x) => audit::failure(format!("Pause failed with err: {}", x))
+    }
+}
+
+async fn start_torrent(client: &dyn TorrentClient, torrents: &[TorrentInfo]) -> String{
+    match client.start(torrents).await{
+        Ok(()) => format!("Started successfully:\n{}", torrent_list(torrents)),
+        Err(x) => audit::failure(format!("Start with err: {}", x))
+    }
+}
+
+async fn remove_torrent(client: &dyn TorrentClient, torrents: &[TorrentInfo], with_data: bool) -> String{
+    match client.remove(torrents, with_data).await{
+        Ok(()) => {
+            if with_data{
+                format!("Removed with data successfully:\n{}", torrent_list(torrents))
             } else {
-                return "Start failed".to_string();
+                format!("Removed without data successfully:\n{}", torrent_list(torrents))
             }
         },
-        Err(x) => format!("Start with err: {}", x)
+        Err(x) => audit::failure(format!("Removed with err: {}", x))
     }
 }
 
-async fn remove_torrent(client: &mut TransClient, id: i64, with_data: bool) -> String{
-    return match client.torrent_remove(vec![types::Id::Id(id)], with_data).await{
+async fn torrent_action(client: &mut TransClient, action: types::TorrentAction, torrents: &[TorrentInfo], done: &str) -> String {
+    match client.torrent_action(action, torrent_ids(torrents)).await {
         Ok(res) => {
-            if res.is_ok(){
-                if with_data{
-                    return "Removed with data successfully".to_string()
-                } else {
-                    return "Removed without data successfully".to_string()
+            if res.is_ok() {
+                format!("{}:\n{}", done, torrent_list(torrents))
+            } else {
+                audit::failure(format!("Failed: {}", res.result))
+            }
+        },
+        Err(x) => audit::failure(format!("Failed with err: {}", x))
+    }
+}
+
+/// Move torrents in the download queue. Queue methods aren't supported by transmission-rpc.
+async fn queue_move(url: &str, direction: &str, torrents: &[TorrentInfo]) -> String {
+    let ids: Vec<i64> = torrents.iter().map(|torrent| torrent.id).collect();
+    match rpc_call(url, &format!("queue-move-{}", direction), serde_json::json!({"ids": ids})).await {
+        Ok(_) => format!("Moved {} in queue:\n{}", direction, torrent_list(torrents)),
+        Err(x) => audit::failure(format!("Failed with err: {}", x))
+    }
+}
+
+/// Move torrent data to the new location.
+async fn move_torrent(client: &mut TransClient, torrents: &[TorrentInfo], location: String) -> String {
+    match client.torrent_set_location(torrent_ids(torrents), location.clone(), Some(true)).await {
+        Ok(res) => {
+            if res.is_ok() {
+                format!("Moved to {}:\n{}", location, torrent_list(torrents))
+            } else {
+                audit::failure(format!("Move failed: {}", res.result))
+            }
+        },
+        Err(x) => audit::failure(format!("Move failed with err: {}", x))
+    }
+}
+
+/// Show torrents matched by bulk removal and ask to confirm it.
+async fn preview_removal(
+    bot: &Bot,
+    upd: &Update,
+    me: &Me,
+    client: Arc<dyn TorrentClient>,
+    torrents: Vec<TorrentInfo>,
+    with_data: bool,
+    confirmation: &Arc<Confirmation>) -> ResponseResult<()> {
+    let Some(chat) = upd.chat() else {
+        return Ok(());
+    };
+    for part in split_message(&format!("Matched {} torrents:\n{}", torrents.len(), torrent_list(&torrents))) {
+        bot.send_message(chat.id, part).await?;
+    }
+    let text = format!("remove {} torrents {}", torrents.len(), if with_data { "with data" } else { "without data" });
+    confirmation.ask(bot, upd, me, text, async move {
+        remove_torrent(client.as_ref(), &torrents, with_data).await
+    }).await
+}
+
+#[derive(Default)]
+struct TorrentState {
+    done: bool,
+    failed: bool,
+    stalled_since: Option<Instant>,
+    stall_reported: bool,
+}
+
+/// Start background task polling torrents and notifying about finished, failed and stalled ones.
+fn start_notifier(bot: Bot, rpc: &str, owners: Owners, config: NotifyConfig) -> Result<(), String> {
+    let url = rpc.parse().map_err(|x| format!("invalid transmission.rpc {}: {}", rpc, x))?;
+    if config.interval == 0 {
+        return Err("transmission.notify.interval must be above 0".to_string());
+    }
+    tokio::spawn(async move {
+        let mut client = TransClient::new(url);
+        let mut states: Option<HashMap<String, TorrentState>> = None;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+        loop {
+            interval.tick().await;
+            let fields = vec![
+                types::TorrentGetField::HashString,
+                types::TorrentGetField::Name,
+                types::TorrentGetField::Status,
+                types::TorrentGetField::Error,
+                types::TorrentGetField::ErrorString,
+                types::TorrentGetField::IsStalled,
+                types::TorrentGetField::PercentDone,
+                types::TorrentGetField::SizeWhenDone,
+                types::TorrentGetField::AddedDate,
+                types::TorrentGetField::DoneDate,
+            ];
+            let torrents = match client.torrent_get(Some(fields), None).await {
+                Ok(res) => res.arguments.torrents,
+                Err(x) => {
+                    log::warn!("Failed to poll transmission: {}", x);
+                    continue;
+                }
+            };
+            // States of the first poll are only remembered, torrents finished before start aren't reported
+            let first = states.is_none();
+            let states = states.get_or_insert_with(HashMap::new);
+            states.retain(|hash, _| torrents.iter().any(|torrent| torrent.hash_string.as_ref() == Some(hash)));
+            owners.lock().unwrap().retain(|hash, _| torrents.iter().any(|torrent| torrent.hash_string.as_ref() == Some(hash)));
+
+            for torrent in &torrents {
+                let Some(hash) = &torrent.hash_string else {
+                    continue;
+                };
+                let state = states.entry(hash.clone()).or_default();
+                let Some(message) = check_torrent(torrent, state, &config) else {
+                    continue;
+                };
+                if first {
+                    continue;
+                }
+                let owner = owners.lock().unwrap().get(hash).copied();
+                let chats = match owner {
+                    Some(chat) => vec![chat],
+                    None => config.chats.iter().map(|chat| ChatId(*chat)).collect(),
+                };
+                for chat in chats {
+                    if let Err(x) = bot.send_message(chat, message.clone()).await {
+                        log::warn!("Failed to send torrent notification to {}: {}", chat, x);
+                    }
                 }
-            }else {
-                return "Removed failed".to_string()
             }
-    },
-        Err(x) => format!("Removed with err: {}", x)
+        }
+    });
+    Ok(())
+}
+
+/// Update torrent state, returns notification when torrent finished, failed or stalled.
+fn check_torrent(torrent: &types::Torrent, state: &mut TorrentState, config: &NotifyConfig) -> Option<String> {
+    let name = torrent.name.clone().unwrap_or("N/A".to_string());
+    let size = format_size(torrent.size_when_done.unwrap_or_default());
+    let added = torrent.added_date.unwrap_or_default();
+    let elapsed = |until: i64| format_duration((until - added).max(0) as u64);
+    let now = chrono::Local::now().timestamp();
+
+    let done = torrent.percent_done.is_some_and(|percent| percent >= 1.0);
+    let failed = matches!(torrent.error, Some(types::ErrorType::TrackerError | types::ErrorType::LocalError));
+    let stalled = !done && torrent.is_stalled == Some(true) && torrent.status == Some(types::TorrentStatus::Downloading);
+
+    let was_done = std::mem::replace(&mut state.done, done);
+    let was_failed = std::mem::replace(&mut state.failed, failed);
+    if stalled {
+        state.stalled_since.get_or_insert_with(Instant::now);
+    } else {
+        state.stalled_since = None;
+        state.stall_reported = false;
+    }
+
+    if done && !was_done {
+        let done_date = torrent.done_date.filter(|date| *date > 0).unwrap_or(now);
+        Some(format!("Torrent finished: {}\nSize: {}\nElapsed: {}", name, size, elapsed(done_date)))
+    } else if failed && !was_failed {
+        Some(format!("Torrent failed: {}\nError: {}\nSize: {}\nElapsed: {}",
+            name,
+            torrent.error_string.clone().unwrap_or_default(),
+            size,
+            elapsed(now)
+        ))
+    } else if !state.stall_reported && state.stalled_since.is_some_and(|since| since.elapsed() >= Duration::from_secs(config.stall_minutes.saturating_mul(60))) {
+        state.stall_reported = true;
+        Some(format!("Torrent stalled: {}\nProgress: {:.0}% of {}\nElapsed: {}",
+            name,
+            torrent.percent_done.unwrap_or_default() * 100.0,
+            size,
+            elapsed(now)
+        ))
+    } else {
+        None
     }
 }
 
 fn get_command_handler_help_text() -> String {
-    let help_text = r#"
+    r#"
 Transmission Command Usage:
 
 /transmission [subcommand] [arguments]
@@ -219,25 +1444,514 @@ Available Subcommands:
 list or "" (empty)
   Lists all torrents in the client.
 
-stop [torrent_id]
-  Stops (pauses) the torrent with the specified ID.
-  Example: /transmission stop 1234
+stop [selector]
+  Stops (pauses) the torrents matched by selector.
+  Example: /transmission stop 1234, /transmission stop all
 
-start [torrent_id]
-  Starts (resumes) the torrent with the specified ID.
-  Example: /transmission start 5678
+start [selector]
+  Starts (resumes) the torrents matched by selector.
+  Example: /transmission start 5678, /transmission start 3-7
 
-remove [torrent_id] [with_data]
-  Removes the torrent with the specified ID from the client.
+remove [selector] [with_data]
+  Removes the torrents matched by selector from the client.
   The 'with_data' argument can be 'yes' or 'no' (or 'y' or 'n') to specify whether to delete the downloaded data or not.
-  Example: /transmission remove 9012 no
+  When selector isn't a single id, matched torrents are shown with buttons to confirm or cancel the removal.
+  Example: /transmission remove 9012 no, /transmission remove completed no
+
+verify [selector]
+  Verifies downloaded data of the torrents.
+
+reannounce [selector]
+  Asks trackers for more peers.
+
+start-now [selector]
+  Starts the torrents bypassing the download queue.
+
+queue [top|up|down|bottom] [selector]
+  Moves the torrents in the download queue.
+  Example: /transmission queue top 1234
+
+move [selector] [path]
+  Moves data of the torrents to the new location.
+  Example: /transmission move 1234 /data/movies
+
+Selector is a torrent id, 'all', 'completed', 'stalled', list of ids and ranges like 1,3-7 or part of the torrent name. Selector made of digits is always treated as ids.
+
+info [torrent_id]
+  Shows details of the torrent: rates, ETA, ratio, peers, trackers status, error, download directory and progress of every file.
+  Example: /transmission info 1234
+
+files [torrent_id]
+  Shows files of the torrent with buttons to skip or download every file and to set its priority (high, normal, low).
+  Example: /transmission files 1234
+
+speed
+  Shows current download and upload rates, speed limits and turtle (alternative speed) mode state.
+
+limit [down|up] [KB/s|off]
+  Sets global download or upload speed limit in KB/s or disables it.
+  Example: /transmission limit down 500
+
+turtle [on|off]
+  Enables or disables turtle mode that applies alternative speed limits.
+  Example: /transmission turtle on
+
+stats
+  Shows downloaded and uploaded totals of the current session and of all time.
+
+add [magnet_or_url] [paused] [download_dir]
+  Adds torrent by magnet link or url of .torrent file. Add 'paused' to add it without starting and path to download it into another directory.
+  Example: /transmission add magnet:?xt=urn:btih:... paused /data/movies
 
 If no subcommand is provided or an invalid subcommand is given, the command will list all torrents by default.
 
-Just drop .torrent file to start downloading it.
+Just drop .torrent file or paste magnet link to start downloading it. Caption of the file selects category from config (download directory and labels), for unknown caption you will be asked to choose one.
+
+With qbittorrent backend only list, stop, start, remove and add are available. qBittorrent has no torrent ids, so torrents are numbered in order they were added. Numbers don't change when other torrents are removed and start from 1 again when the bot restarts.
 
 Note: Replace [torrent_id] with the actual ID of the torrent you want to operate on.
-"#.to_string();
+"#.to_string()
+}
 
-    help_text
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{me, message};
+
+    /// Client with a fixed list of torrents, other operations aren't supported.
+    struct FakeClient;
+
+    #[async_trait]
+    impl TorrentClient for FakeClient {
+        fn backend(&self) -> Backend {
+            Backend::Transmission
+        }
+
+        async fn list(&self) -> Result<Vec<TorrentInfo>, String> {
+            Ok([(5, "Ubuntu.iso", 1.0), (6, "Debian.iso", 0.5), (7, "Arch.iso", 1.0)].into_iter()
+                .map(|(id, name, percent_done)| TorrentInfo {
+                    id,
+                    hash: format!("hash{}", id),
+                    name: name.to_string(),
+                    status: "Seeding".to_string(),
+                    percent_done,
+                    stalled: false,
+                })
+                .collect())
+        }
+
+        async fn add(&self, _source: TorrentSource, _options: AddOptions) -> Result<Added, String> {
+            Err("not supported in test".into())
+        }
+
+        async fn start(&self, _torrents: &[TorrentInfo]) -> Result<(), String> {
+            Err("not supported in test".into())
+        }
+
+        async fn stop(&self, _torrents: &[TorrentInfo]) -> Result<(), String> {
+            Err("not supported in test".into())
+        }
+
+        async fn remove(&self, _torrents: &[TorrentInfo], _with_data: bool) -> Result<(), String> {
+            Err("not supported in test".into())
+        }
+    }
+
+    fn plugin() -> TransmissionPlugin {
+        let plugin = TransmissionPlugin::default();
+        let _ = plugin.client.set(Arc::new(FakeClient));
+        plugin
+    }
+
+    #[tokio::test]
+    async fn selectors_resolve_to_torrent_ids() {
+        let plugin = plugin();
+        let resolve = |permission: &str| plugin.resolve(permission.to_string());
+        assert_eq!(resolve("transmission.remove.5-6.no").await, ["transmission.remove.5.no", "transmission.remove.6.no"]);
+        assert_eq!(resolve("transmission.remove.5,5.yes").await, ["transmission.remove.5.yes"]);
+        assert_eq!(resolve("transmission.stop.ubuntu").await, ["transmission.stop.5"]);
+        assert_eq!(resolve("transmission.stop.debian%2Eiso").await, ["transmission.stop.6"]);
+        assert_eq!(resolve("transmission.start.completed").await, ["transmission.start.5", "transmission.start.7"]);
+        assert_eq!(resolve("transmission.queue.top.7").await, ["transmission.queue.top.7"]);
+        assert_eq!(resolve("transmission.move.0006./data").await, ["transmission.move.6./data"]);
+        assert_eq!(resolve("transmission.info.005").await, ["transmission.info.5"]);
+    }
+
+    #[test]
+    fn removal_flag_ignores_case() {
+        assert_eq!(removal_flag("YES"), "yes");
+        assert_eq!(removal_flag("Y"), "y");
+        assert_eq!(removal_flag("No"), "no");
+        assert_eq!(removal_flag("n"), "n");
+        assert_eq!(removal_flag("whatever"), "no");
+    }
+
+    #[test]
+    fn only_selectors_are_previewed_before_removal() {
+        assert!(!previews_removal(&["remove", "5", "yes"]));
+        assert!(previews_removal(&["remove", "5,5", "yes"]));
+        assert!(previews_removal(&["remove", "all", "no"]));
+        assert!(previews_removal(&["remove", "ubuntu", "server", "no"]));
+        assert!(!previews_removal(&["remove", "5"]));
+        assert!(!previews_removal(&["stop", "all"]));
+    }
+
+    fn notify_config(yaml: &str) -> NotifyConfig {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    fn torrent(percent_done: f64, error: u8, stalled: bool) -> types::Torrent {
+        serde_json::from_value(serde_json::json!({
+            "name": "Ubuntu",
+            "sizeWhenDone": 1536,
+            "addedDate": 1000,
+            "doneDate": 1090,
+            "percentDone": percent_done,
+            "error": error,
+            "errorString": if error == 0 { "" } else { "Tracker gone" },
+            "isStalled": stalled,
+            "status": 4
+        })).unwrap()
+    }
+
+    #[test]
+    fn finished_torrent_is_reported_once() {
+        let config = notify_config("{}");
+        let mut state = TorrentState::default();
+        assert_eq!(check_torrent(&torrent(0.5, 0, false), &mut state, &config), None);
+        assert_eq!(check_torrent(&torrent(1.0, 0, false), &mut state, &config).as_deref(),
+            Some("Torrent finished: Ubuntu\nSize: 1.50 KB\nElapsed: 1m 30s"));
+        assert_eq!(check_torrent(&torrent(1.0, 0, false), &mut state, &config), None);
+    }
+
+    #[test]
+    fn failed_torrent_is_reported_once() {
+        let config = notify_config("{}");
+        let mut state = TorrentState::default();
+        let message = check_torrent(&torrent(0.5, 2, false), &mut state, &config).unwrap();
+        assert!(message.starts_with("Torrent failed: Ubuntu\nError: Tracker gone\n"));
+        assert_eq!(check_torrent(&torrent(0.5, 2, false), &mut state, &config), None);
+        // Tracker warnings aren't failures
+        assert_eq!(check_torrent(&torrent(0.5, 1, false), &mut TorrentState::default(), &config), None);
+    }
+
+    #[test]
+    fn stalled_torrent_is_reported_after_stall_minutes() {
+        let mut state = TorrentState::default();
+        let message = check_torrent(&torrent(0.25, 0, true), &mut state, &notify_config("stall_minutes: 0")).unwrap();
+        assert!(message.starts_with("Torrent stalled: Ubuntu\nProgress: 25% of 1.50 KB\n"));
+        assert_eq!(check_torrent(&torrent(0.25, 0, true), &mut state, &notify_config("stall_minutes: 0")), None);
+
+        let mut state = TorrentState::default();
+        assert_eq!(check_torrent(&torrent(0.25, 0, true), &mut state, &notify_config("{}")), None);
+        assert!(state.stalled_since.is_some());
+        assert_eq!(check_torrent(&torrent(0.25, 0, false), &mut state, &notify_config("{}")), None);
+        assert!(state.stalled_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn zero_notify_interval_is_rejected() {
+        let result = start_notifier(Bot::new("token"), "http://127.0.0.1:9091/transmission/rpc", Owners::default(), notify_config("interval: 0"));
+        assert_eq!(result.unwrap_err(), "transmission.notify.interval must be above 0");
+    }
+
+    #[test]
+    fn limit_args_set_one_direction() {
+        let args = parse_limit_args(&["down", "500"]).unwrap();
+        assert_eq!((args.speed_limit_down_enabled, args.speed_limit_down), (Some(true), Some(500)));
+        assert_eq!((args.speed_limit_up_enabled, args.speed_limit_up), (None, None));
+
+        let args = parse_limit_args(&["up", "off"]).unwrap();
+        assert_eq!((args.speed_limit_up_enabled, args.speed_limit_up), (Some(false), None));
+        assert_eq!(args.speed_limit_down_enabled, None);
+    }
+
+    #[test]
+    fn invalid_limit_args_are_rejected() {
+        assert_eq!(parse_limit_args(&["down"]).unwrap_err(), "Please provide direction (down, up) and limit in KB/s or off");
+        assert_eq!(parse_limit_args(&["down", "fast"]).unwrap_err(), "Invalid limit fast");
+        assert_eq!(parse_limit_args(&["down", "-5"]).unwrap_err(), "Invalid limit -5");
+        assert_eq!(parse_limit_args(&["down", "99999999999"]).unwrap_err(), "Invalid limit 99999999999");
+        assert_eq!(parse_limit_args(&["sideways", "5"]).unwrap_err(), "Unknown direction sideways, must be down or up");
+    }
+
+    fn torrents() -> Vec<TorrentInfo> {
+        [(1, "1984", 1.0, false), (2, "Ubuntu", 0.5, true), (3, "ubuntu server", 1.0, false)].into_iter()
+            .map(|(id, name, percent_done, stalled)| TorrentInfo {
+                id,
+                hash: format!("hash{}", id),
+                name: name.to_string(),
+                status: "Seeding".to_string(),
+                percent_done,
+                stalled,
+            })
+            .collect()
+    }
+
+    fn selected(selector: &str) -> Result<Vec<i64>, String> {
+        filter_torrents(torrents(), selector).map(|torrents| torrents.iter().map(|torrent| torrent.id).collect())
+    }
+
+    #[test]
+    fn ids_and_ranges_are_parsed() {
+        assert_eq!(parse_ids("5"), Ok(Some(vec![5..=5])));
+        assert_eq!(parse_ids("1,3-7"), Ok(Some(vec![1..=1, 3..=7])));
+        assert_eq!(parse_ids("1,,2"), Ok(Some(vec![1..=1, 2..=2])));
+        assert_eq!(parse_ids("5-5"), Ok(Some(vec![5..=5])));
+        assert_eq!(parse_ids("ubuntu"), Ok(None));
+        assert_eq!(parse_ids(""), Ok(None));
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        assert_eq!(parse_ids("-"), Err("Invalid range -".to_string()));
+        assert_eq!(parse_ids("5-3"), Err("Invalid range 5-3".to_string()));
+        assert_eq!(parse_ids("5-"), Err("Invalid range 5-".to_string()));
+        assert_eq!(parse_ids("1-2-3"), Err("Invalid range 1-2-3".to_string()));
+        assert_eq!(parse_ids(","), Err("Invalid torrent ids ,".to_string()));
+        assert_eq!(parse_ids("99999999999999999999"), Err("Invalid torrent id 99999999999999999999".to_string()));
+    }
+
+    #[test]
+    fn torrents_are_selected() {
+        assert_eq!(selected("all"), Ok(vec![1, 2, 3]));
+        assert_eq!(selected("completed"), Ok(vec![1, 3]));
+        assert_eq!(selected("stalled"), Ok(vec![2]));
+        assert_eq!(selected("2-3"), Ok(vec![2, 3]));
+        assert_eq!(selected("UBUNTU"), Ok(vec![2, 3]));
+        assert_eq!(selected("ubuntu server"), Ok(vec![3]));
+        assert_eq!(selected("5-9"), Err("No torrents match 5-9".to_string()));
+        assert_eq!(selected("5-3"), Err("Invalid range 5-3".to_string()));
+    }
+
+    #[test]
+    fn numeric_selector_is_id_not_name() {
+        assert_eq!(selected("1984"), Err("No torrents match 1984".to_string()));
+        assert_eq!(selected("1"), Ok(vec![1]));
+    }
+
+    fn add_args(com: &[&str]) -> Result<(String, Option<String>, bool, Vec<String>), String> {
+        let (source, options) = parse_add_args(com)?;
+        let TorrentSource::Link(link) = source else {
+            return Err("Not a link".to_string());
+        };
+        Ok((link, options.download_dir, options.paused, options.labels))
+    }
+
+    #[test]
+    fn add_args_are_parsed() {
+        let link = |link: &str, download_dir: Option<&str>, paused: bool| Ok((link.to_string(), download_dir.map(|dir| dir.to_string()), paused, Vec::new()));
+        assert_eq!(add_args(&["magnet:?xt=urn:btih:abc"]), link("magnet:?xt=urn:btih:abc", None, false));
+        assert_eq!(add_args(&["https://example.com/a.torrent"]), link("https://example.com/a.torrent", None, false));
+        assert_eq!(add_args(&["http://example.com/a.torrent", "paused"]), link("http://example.com/a.torrent", None, true));
+        assert_eq!(add_args(&["magnet:?xt=urn:btih:abc", "/data/movies"]), link("magnet:?xt=urn:btih:abc", Some("/data/movies"), false));
+        assert_eq!(add_args(&["magnet:?xt=urn:btih:abc", "paused", "/data/movies"]), link("magnet:?xt=urn:btih:abc", Some("/data/movies"), true));
+        assert_eq!(add_args(&["magnet:?xt=urn:btih:abc", "/data/movies", "paused"]), link("magnet:?xt=urn:btih:abc", Some("/data/movies"), true));
+        assert_eq!(add_args(&["", "magnet:?xt=urn:btih:abc", "", "paused"]), link("magnet:?xt=urn:btih:abc", None, true));
+    }
+
+    #[test]
+    fn invalid_add_args_are_rejected() {
+        assert_eq!(add_args(&[]), Err("Please provide magnet link or url".to_string()));
+        assert_eq!(add_args(&[""]), Err("Please provide magnet link or url".to_string()));
+        assert_eq!(add_args(&["ftp://example.com/a.torrent"]), Err("ftp://example.com/a.torrent is not a magnet link or url".to_string()));
+        assert_eq!(add_args(&["magnet:xt=urn:btih:abc"]), Err("magnet:xt=urn:btih:abc is not a magnet link or url".to_string()));
+        assert_eq!(add_args(&["ubuntu.torrent"]), Err("ubuntu.torrent is not a magnet link or url".to_string()));
+        assert_eq!(add_args(&["magnet:?xt=urn:btih:abc", "/data", "/other"]), Err("Unexpected argument /other".to_string()));
+    }
+
+    fn text_message(text: &str) -> Message {
+        let UpdateKind::Message(msg) = message(7, 7, text).kind else {
+            unreachable!()
+        };
+        msg
+    }
+
+    #[test]
+    fn only_magnet_links_are_added_from_text() {
+        assert!(magnet_filter(&text_message("magnet:?xt=urn:btih:abc")));
+        assert!(magnet_filter(&text_message("  magnet:?xt=urn:btih:abc")));
+        assert!(!magnet_filter(&text_message("https://example.com/a.torrent")));
+        assert!(!magnet_filter(&text_message("look at magnet:?xt=urn:btih:abc")));
+        assert!(!magnet_filter(&text_message("/transmission add magnet:?xt=urn:btih:abc")));
+        assert!(!file_filter(&text_message("magnet:?xt=urn:btih:abc")));
+    }
+
+    #[tokio::test]
+    async fn detail_commands_resolve_to_torrent_ids() {
+        let plugin = plugin();
+        let resolve = |permission: &str| plugin.resolve(permission.to_string());
+        assert_eq!(resolve("transmission.verify.5-6").await, ["transmission.verify.5", "transmission.verify.6"]);
+        assert_eq!(resolve("transmission.reannounce.6-7").await, ["transmission.reannounce.6", "transmission.reannounce.7"]);
+        assert_eq!(resolve("transmission.queue.up.5,7").await, ["transmission.queue.up.5", "transmission.queue.up.7"]);
+        assert_eq!(resolve("transmission.queue.bottom.5-7").await, ["transmission.queue.bottom.5", "transmission.queue.bottom.6", "transmission.queue.bottom.7"]);
+        assert_eq!(resolve("transmission.files.06").await, ["transmission.files.6"]);
+        // info and files take a single id, ranges are rejected by the command and kept as they are
+        assert_eq!(resolve("transmission.info.5-6").await, ["transmission.info.5-6"]);
+        assert_eq!(resolve("transmission.files.5-6").await, ["transmission.files.5-6"]);
+    }
+
+    fn files_torrent(count: usize) -> types::Torrent {
+        let files: Vec<serde_json::Value> = (0..count)
+            .map(|index| serde_json::json!({"name": format!("file{}.mkv", index), "length": 1024, "bytesCompleted": if index == 0 { 512 } else { 0 }}))
+            .collect();
+        let stats: Vec<serde_json::Value> = (0..count)
+            .map(|index| serde_json::json!({"bytesCompleted": 0, "wanted": index != 1, "priority": if index == 0 { 1 } else { 0 }}))
+            .collect();
+        serde_json::from_value(serde_json::json!({"name": "Show", "files": files, "fileStats": stats})).unwrap()
+    }
+
+    fn callbacks(keyboard: &InlineKeyboardMarkup) -> Vec<Vec<(String, String)>> {
+        keyboard.inline_keyboard.iter()
+            .map(|row| row.iter().map(|button| match &button.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => (button.text.clone(), data.clone()),
+                _ => (button.text.clone(), String::new()),
+            }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn files_are_listed_with_buttons() {
+        let (text, keyboard) = files_page(5, files_torrent(2), 0).unwrap();
+        assert_eq!(text, "Files of Show (page 1/1):\n1. [x] 50% file0.mkv (1.00 KB)\n2. [ ] 0% file1.mkv (1.00 KB)\n");
+        let buttons = callbacks(&keyboard);
+        assert_eq!(buttons[0], [
+            ("1: skip".to_string(), "tr:files:5:0:unwanted".to_string()),
+            ("• High".to_string(), "tr:files:5:0:high".to_string()),
+            ("Normal".to_string(), "tr:files:5:0:normal".to_string()),
+            ("Low".to_string(), "tr:files:5:0:low".to_string()),
+        ]);
+        assert_eq!(buttons[1][0], ("2: download".to_string(), "tr:files:5:1:wanted".to_string()));
+        assert_eq!(buttons[1][2].0, "• Normal");
+        assert_eq!(buttons.len(), 2);
+    }
+
+    #[test]
+    fn files_are_paged() {
+        let (text, keyboard) = files_page(5, files_torrent(25), 0).unwrap();
+        assert!(text.starts_with("Files of Show (page 1/3):\n"));
+        let buttons = callbacks(&keyboard);
+        assert_eq!(buttons.len(), FILES_PER_PAGE + 1);
+        assert_eq!(buttons[FILES_PER_PAGE], [("Next »".to_string(), "tr:files:5:page:1".to_string())]);
+
+        let (text, keyboard) = files_page(5, files_torrent(25), 1).unwrap();
+        assert!(text.starts_with("Files of Show (page 2/3):\n11. [x] 0% file10.mkv"));
+        assert_eq!(callbacks(&keyboard)[FILES_PER_PAGE].iter().map(|(_, data)| data.as_str()).collect::<Vec<&str>>(), ["tr:files:5:page:0", "tr:files:5:page:2"]);
+
+        // Page after the last one shows the last page
+        let (text, keyboard) = files_page(5, files_torrent(25), 9).unwrap();
+        assert!(text.starts_with("Files of Show (page 3/3):\n21. [x] 0% file20.mkv"));
+        let buttons = callbacks(&keyboard);
+        assert_eq!(buttons.len(), 6);
+        assert_eq!(buttons[5], [("« Prev".to_string(), "tr:files:5:page:1".to_string())]);
+    }
+
+    #[test]
+    fn torrent_without_files_is_reported() {
+        assert_eq!(files_page(5, files_torrent(0), 0).unwrap_err(), "Torrent has no files yet");
+    }
+
+    /// Torrents the handler would select for the command and whether the role allowed to stop only torrent 5 may run it.
+    async fn stop_as_limited_role(text: &str) -> (Vec<i64>, bool) {
+        let plugin = plugin();
+        let role = serde_yml::from_str("{allow: [transmission.stop.5], users: [7]}").unwrap();
+        let access_control = crate::access::AccessControl::new(None, Some(HashMap::from([("stopper".to_string(), role)]))).unwrap();
+        let upd = message(7, 7, text);
+        let permission = plugin.permission(&upd, &me()).unwrap();
+        let permissions = plugin.resolve(permission).await;
+        let allowed = access_control.allows_all(&upd, Some(&permissions));
+
+        let Ok(Command::Transmission(command)) = Command::parse(text, "captain_bot") else {
+            panic!("{} is not a transmission command", text);
+        };
+        let com = arguments(&command);
+        let selected = select_torrents(&FakeClient, &resolved_selector(&com, &Resolved(Some(permissions)))).await.unwrap();
+        (selected.iter().map(|torrent| torrent.id).collect(), allowed)
+    }
+
+    #[tokio::test]
+    async fn handler_selects_torrents_checked_by_permission() {
+        assert_eq!(stop_as_limited_role("/transmission stop 5").await, (vec![5], true));
+        assert_eq!(stop_as_limited_role("/transmission stop  5").await, (vec![5], true));
+        assert_eq!(stop_as_limited_role("/transmission  stop\t5 ").await, (vec![5], true));
+        assert_eq!(stop_as_limited_role("/transmission stop ubuntu").await, (vec![5], true));
+        assert_eq!(stop_as_limited_role("/transmission stop  debian").await, (vec![6], false));
+        assert_eq!(stop_as_limited_role("/transmission stop  iso").await, (vec![5, 6, 7], false));
+        assert_eq!(stop_as_limited_role("/transmission stop 5-6").await, (vec![5, 6], false));
+    }
+
+    #[test]
+    fn handler_and_confirmation_agree_on_preview() {
+        let plugin = plugin();
+        for text in ["/transmission remove  5 yes", "/transmission remove 5  yes", "/transmission remove  all no"] {
+            let Ok(Command::Transmission(command)) = Command::parse(text, "captain_bot") else {
+                panic!("{} is not a transmission command", text);
+            };
+            assert_eq!(previews_removal(&arguments(&command)), plugin.confirms(&message(7, 7, text), &me()), "{}", text);
+        }
+    }
+
+    fn categories() -> HashMap<String, Category> {
+        let long = "x".repeat(100);
+        serde_yml::from_str(&format!("{{movies: {{download_dir: /data/movies, labels: [film]}}, tv: {{labels: [series]}}, {}: {{download_dir: /data/long}}}}", long)).unwrap()
+    }
+
+    fn route(options: Option<AddOptions>) -> Option<(Option<String>, Vec<String>)> {
+        options.map(|options| (options.download_dir, options.labels))
+    }
+
+    #[test]
+    fn dropped_files_are_routed_by_caption() {
+        let categories = categories();
+        assert_eq!(route(caption_options(&categories, Some("movies"))), Some((Some("/data/movies".to_string()), vec!["film".to_string()])));
+        assert_eq!(route(caption_options(&categories, Some(" TV "))), Some((None, vec!["series".to_string()])));
+        assert_eq!(route(caption_options(&categories, None)), Some((None, Vec::new())));
+        assert_eq!(route(caption_options(&categories, Some("  "))), Some((None, Vec::new())));
+        assert_eq!(route(caption_options(&categories, Some("music"))), None);
+        // Captions are ignored without categories
+        assert_eq!(route(caption_options(&HashMap::new(), Some("music"))), Some((None, Vec::new())));
+    }
+
+    #[test]
+    fn category_is_chosen_by_button_index() {
+        let categories = categories();
+        let keyboard = category_keyboard(&categories, 12345678901234567890);
+        let buttons = callbacks(&keyboard);
+        assert_eq!(buttons[0].iter().map(|(text, _)| text.as_str()).collect::<Vec<&str>>(), ["movies", "tv", &"x".repeat(100)]);
+        assert!(buttons.iter().flatten().all(|(_, data)| data.len() <= 64));
+
+        let choice = |data: &str| route(chosen_options(&categories, data.rsplit(':').next().unwrap()));
+        assert_eq!(choice(&buttons[0][0].1), Some((Some("/data/movies".to_string()), vec!["film".to_string()])));
+        assert_eq!(choice(&buttons[0][2].1), Some((Some("/data/long".to_string()), Vec::new())));
+        assert_eq!(buttons[1][0], ("Default".to_string(), "tr:category:12345678901234567890:".to_string()));
+        assert_eq!(choice(&buttons[1][0].1), Some((None, Vec::new())));
+        assert_eq!(buttons[1][1].1, "tr:category:12345678901234567890:cancel");
+        assert_eq!(choice("3"), None);
+        assert_eq!(choice("movies"), None);
+    }
+
+    #[tokio::test]
+    async fn permissions_without_selector_are_kept() {
+        let plugin = plugin();
+        assert_eq!(plugin.resolve("transmission.list".to_string()).await, ["transmission.list"]);
+        assert_eq!(plugin.resolve("transmission.add".to_string()).await, ["transmission.add"]);
+    }
+
+    #[tokio::test]
+    async fn unresolved_selector_is_denied() {
+        let plugin = plugin();
+        assert!(plugin.resolve("transmission.stop.nothing".to_string()).await.is_empty());
+        assert!(plugin.resolve("transmission.remove.0-1.yes".to_string()).await.is_empty());
+    }
+
+    #[test]
+    fn handler_uses_resolved_torrents() {
+        let resolved = Resolved(Some(vec!["transmission.remove.5.no".to_string(), "transmission.remove.6.no".to_string()]));
+        assert_eq!(resolved_selector(&["remove", "iso", "server", "no"], &resolved), "5,6");
+        let resolved = Resolved(Some(vec!["transmission.queue.top.7".to_string()]));
+        assert_eq!(resolved_selector(&["queue", "top", "arch"], &resolved), "7");
+        // Without access control the selector is used as is
+        assert_eq!(resolved_selector(&["stop", "iso", "server"], &Resolved::default()), "iso server");
+        assert_eq!(resolved_selector(&["move", "arch", "/data"], &Resolved::default()), "arch");
+    }
 }
@@ -0,0 +1,248 @@
+use teloxide::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::utils::format_duration;
+
+/// `sys.uptime` config section.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UptimeConfig {
+    /// Seconds between checks.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    chats: Vec<i64>,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+pub struct ServiceState {
+    up: bool,
+    /// Time of the last state transition.
+    since: Instant,
+    latency: Option<u128>,
+    error: Option<String>,
+    checks: u64,
+    successes: u64,
+    last_check: chrono::DateTime<chrono::Local>,
+}
+
+/// State of services from `sys.ping` shared between the checker task and `/sys ping`.
+pub type Services = Arc<Mutex<HashMap<String, ServiceState>>>;
+
+/// Time after which a service that did not respond is considered down.
+const TIMEOUT: Duration = Duration::from_secs(12);
+
+/// Check service with address like `http:127.0.0.1:9090`, returns latency in milliseconds.
+pub async fn check(address: &str) -> Result<u128, String> {
+    probe(address, TIMEOUT).await
+}
+
+async fn probe(address: &str, timeout: Duration) -> Result<u128, String> {
+    let addr: Vec<&str> = address.split(':').collect();
+    if addr.len() < 3 {
+        return Err(format!("Invalid address {}", address));
+    }
+    let port: u32 = addr[2].parse().map_err(|_| format!("Invalid port {}", addr[2]))?;
+    let client = reqwest::Client::builder().timeout(timeout).build().map_err(|x| x.to_string())?;
+    let started = Instant::now();
+    match client.get(format!("{}://{}:{}", addr[0], addr[1], port)).send().await {
+        Ok(rsp) if rsp.status().is_success() => Ok(started.elapsed().as_millis()),
+        Ok(rsp) => Err(format!("HTTP {}", rsp.status())),
+        Err(x) if x.is_timeout() => Err(format!("No response within {}", format_duration(timeout.as_secs()))),
+        Err(x) => Err(x.to_string()),
+    }
+}
+
+/// Start background task checking services and notifying chats about up/down transitions.
+pub fn start(bot: Bot, services: Services, targets: HashMap<String, String>, config: UptimeConfig) -> Result<(), String> {
+    if config.chats.is_empty() {
+        return Err("sys.uptime.chats is empty".to_string());
+    }
+    if config.interval == 0 {
+        return Err("sys.uptime.interval must be above 0".to_string());
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+        loop {
+            interval.tick().await;
+            for (name, address) in &targets {
+                let result = check(address).await;
+                if let Some(message) = update(&services, name, result) {
+                    for chat in &config.chats {
+                        if let Err(x) = bot.send_message(ChatId(*chat), message.clone()).await {
+                            log::warn!("Failed to send uptime notification to {}: {}", chat, x);
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Save check result, returns notification when service state changed.
+fn update(services: &Services, name: &str, result: Result<u128, String>) -> Option<String> {
+    let mut services = services.lock().unwrap();
+    let up = result.is_ok();
+    let now = Instant::now();
+    let previous = services.get(name).map(|state| (state.up, state.since));
+
+    let state = services.entry(name.to_string()).or_insert_with(|| ServiceState {
+        up,
+        since: now,
+        latency: None,
+        error: None,
+        checks: 0,
+        successes: 0,
+        last_check: chrono::Local::now(),
+    });
+    state.checks += 1;
+    state.last_check = chrono::Local::now();
+    match result {
+        Ok(latency) => {
+            state.successes += 1;
+            state.latency = Some(latency);
+            state.error = None;
+        },
+        Err(x) => {
+            state.latency = None;
+            state.error = Some(x);
+        }
+    }
+
+    match previous {
+        Some((was_up, since)) if was_up != up => {
+            state.up = up;
+            state.since = now;
+            let duration = format_duration(now.duration_since(since).as_secs());
+            if up {
+                Some(format!("{} is UP after {} of downtime", name, duration))
+            } else {
+                Some(format!("{} is DOWN after {} of uptime: {}", name, duration, state.error.clone().unwrap_or_default()))
+            }
+        },
+        // Report services that are down already on the first check
+        None if !up => Some(format!("{} is DOWN: {}", name, state.error.clone().unwrap_or_default())),
+        _ => None,
+    }
+}
+
+/// State of services for `/sys ping`.
+pub fn report(services: &Services) -> String {
+    let services = services.lock().unwrap();
+    if services.is_empty() {
+        return "No checks yet".to_string();
+    }
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    let mut data = String::new();
+    for name in names {
+        let state = &services[name];
+        let latency = state.latency.map(|latency| format!("{} ms", latency)).unwrap_or("N/A".to_string());
+        data += format!("{}: {} for {} (latency: {}, uptime: {:.2}%, checked: {})\n",
+            name,
+            if state.up { "OK" } else { "BAD" },
+            format_duration(state.since.elapsed().as_secs()),
+            latency,
+            state.successes as f64 / state.checks as f64 * 100.0,
+            state.last_check.format("%H:%M:%S")
+        ).as_str();
+        if let Some(error) = &state.error {
+            data += format!("  {}\n", error).as_str();
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn transitions_are_reported() {
+        let services = Services::default();
+        assert_eq!(update(&services, "web", Ok(10)), None);
+        assert_eq!(update(&services, "web", Ok(12)), None);
+        assert_eq!(update(&services, "web", Err("timeout".to_string())).as_deref(), Some("web is DOWN after 0s of uptime: timeout"));
+        assert_eq!(update(&services, "web", Err("timeout".to_string())), None);
+        assert_eq!(update(&services, "web", Ok(11)).as_deref(), Some("web is UP after 0s of downtime"));
+
+        let services = services.lock().unwrap();
+        let state = &services["web"];
+        assert!(state.up);
+        assert_eq!((state.checks, state.successes, state.latency), (5, 3, Some(11)));
+        assert!(state.error.is_none());
+    }
+
+    #[test]
+    fn service_down_on_first_check_is_reported() {
+        let services = Services::default();
+        assert_eq!(update(&services, "db", Err("refused".to_string())).as_deref(), Some("db is DOWN: refused"));
+        assert_eq!(update(&services, "db", Err("refused".to_string())), None);
+    }
+
+    #[test]
+    fn report_lists_services_by_name() {
+        let services = Services::default();
+        assert_eq!(report(&services), "No checks yet");
+        update(&services, "web", Ok(10));
+        update(&services, "db", Err("refused".to_string()));
+        let report = report(&services);
+        assert!(report.starts_with("db: BAD for 0s (latency: N/A, uptime: 0.00%"));
+        assert!(report.contains("\n  refused\nweb: OK for 0s (latency: 10 ms, uptime: 100.00%"));
+    }
+
+    #[tokio::test]
+    async fn invalid_address_is_rejected() {
+        assert_eq!(check("http:127.0.0.1").await.unwrap_err(), "Invalid address http:127.0.0.1");
+        assert_eq!(check("http:127.0.0.1:port").await.unwrap_err(), "Invalid port port");
+    }
+
+    /// Serve one connection with `response`, or keep it open without answering when `None`.
+    fn serve(response: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http:127.0.0.1:{}", listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 4096];
+            let _ = stream.read(&mut buffer);
+            match response {
+                Some(response) => stream.write_all(response.as_bytes()).unwrap(),
+                None => thread::sleep(Duration::from_secs(5)),
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn ok_response_is_up() {
+        let address = serve(Some("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nOk"));
+        assert!(probe(&address, Duration::from_secs(5)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn error_response_is_down() {
+        let address = serve(Some("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 5\r\nConnection: close\r\n\r\nError"));
+        assert_eq!(probe(&address, Duration::from_secs(5)).await.unwrap_err(), "HTTP 500 Internal Server Error");
+    }
+
+    #[tokio::test]
+    async fn silent_service_is_down() {
+        let address = serve(None);
+        assert_eq!(probe(&address, Duration::from_secs(1)).await.unwrap_err(), "No response within 1s");
+    }
+
+    #[tokio::test]
+    async fn zero_interval_is_rejected() {
+        let config: UptimeConfig = serde_yml::from_str("interval: 0\nchats: [1]").unwrap();
+        let result = start(Bot::new("token"), Services::default(), HashMap::new(), config);
+        assert_eq!(result.unwrap_err(), "sys.uptime.interval must be above 0");
+    }
+}
@@ -0,0 +1,142 @@
+/// Human readable duration like `1d 2h 3m 4s`.
+pub fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes, seconds) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Human readable size like `1.50 GB`.
+pub fn format_size(bytes: i64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.2} {}", size, units[unit])
+    }
+}
+
+/// Maximal length of telegram message.
+pub const MESSAGE_LIMIT: usize = 4096;
+
+/// Maximal length of inline keyboard button callback data in bytes.
+pub const CALLBACK_DATA_LIMIT: usize = 64;
+
+/// Split text into parts fitting into telegram message. Lines are kept whole unless a single line is too long.
+pub fn split_message(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut length = 0;
+    for line in text.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut chunks: Vec<&[char]> = chars.chunks(MESSAGE_LIMIT - 1).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        for chunk in chunks {
+            if length + chunk.len() + 1 > MESSAGE_LIMIT {
+                parts.push(std::mem::take(&mut part));
+                length = 0;
+            }
+            part.extend(chunk);
+            part.push('\n');
+            length += chunk.len() + 1;
+        }
+    }
+    if !part.trim().is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// Parse duration like `30s`, `10m`, `2h` or `1d` into seconds. Number without unit is seconds.
+/// Returns `None` for invalid or too big durations.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last()? {
+        's' => (&value[..value.len() - 1], 1),
+        'm' => (&value[..value.len() - 1], 60),
+        'h' => (&value[..value.len() - 1], 3600),
+        'd' => (&value[..value.len() - 1], 86400),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok().and_then(|number| number.checked_mul(multiplier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_formatted() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(90), "1m 30s");
+        assert_eq!(format_duration(3725), "1h 2m 5s");
+        assert_eq!(format_duration(90061), "1d 1h 1m");
+    }
+
+    #[test]
+    fn sizes_are_formatted() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.50 KB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.00 GB");
+        assert_eq!(format_size(2048 * 1024 * 1024 * 1024 * 1024), "2048.00 TB");
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30"), Some(30));
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("10m"), Some(600));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1d"), Some(86400));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("5w"), None);
+        assert_eq!(parse_duration("10é"), None);
+    }
+
+    #[test]
+    fn overflowing_duration_is_rejected() {
+        assert_eq!(parse_duration("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_duration("18446744073709551615d"), None);
+        assert_eq!(parse_duration("213503982334601d"), Some(213503982334601 * 86400));
+        assert_eq!(parse_duration("213503982334602d"), None);
+    }
+
+    #[test]
+    fn short_message_is_kept() {
+        assert_eq!(split_message("a\nb"), ["a\nb\n"]);
+        assert!(split_message("").is_empty());
+        assert!(split_message("\n\n").is_empty());
+    }
+
+    #[test]
+    fn long_message_is_split_by_lines() {
+        let line = "x".repeat(3000);
+        let parts = split_message(&format!("{}\n{}\n{}", line, line, line));
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part == &format!("{}\n", line)));
+    }
+
+    #[test]
+    fn long_line_is_split_by_chars() {
+        let parts = split_message(&"é".repeat(MESSAGE_LIMIT * 2));
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.chars().count() <= MESSAGE_LIMIT));
+        assert_eq!(parts.concat().chars().filter(|char| *char == 'é').count(), MESSAGE_LIMIT * 2);
+    }
+}